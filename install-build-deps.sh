@@ -1,4 +1,4 @@
 #!/usr/bin/env bash
 
 sudo apt --quiet --yes install \
-    libudev-dev pulseaudio libdbus-1-dev
\ No newline at end of file
+    libudev-dev pulseaudio libdbus-1-dev libxss-dev libpipewire-0.3-dev clang
\ No newline at end of file
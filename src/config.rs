@@ -1,44 +1,799 @@
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
+mod migration;
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Config {
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// Config structure version, older files are migrated automatically
+    pub version: u32,
     pub device: Device,
-    pub layouts: Vec<String>,
-    pub reconnect_delay: u64,
+    #[serde(default)]
+    pub providers: Providers,
+    #[serde(default)]
+    pub control: Control,
+    #[serde(default)]
+    pub script: ScriptConfig,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Device {
+    /// `0` matches any vendor
+    #[serde(with = "hex", default)]
+    #[schemars(schema_with = "hex::schema")]
     pub vendor_id: u16,
+    #[serde(with = "hex")]
+    #[schemars(schema_with = "hex::schema")]
     pub product_id: u16,
+    #[serde(with = "hex")]
+    #[schemars(schema_with = "hex::schema")]
     pub usage: u16,
+    #[serde(with = "hex")]
+    #[schemars(schema_with = "hex::schema")]
     pub usage_page: u16,
+    /// Delay between reconnecting attempts in milliseconds
+    #[serde(default = "default_reconnect_delay")]
+    pub reconnect_delay: u64,
+    /// Interval between heartbeat messages in milliseconds, `0` disables them
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+}
+
+fn default_reconnect_delay() -> u64 {
+    return 5000;
+}
+
+fn default_heartbeat_interval() -> u64 {
+    return 5000;
+}
+
+/// Local control server, used by CLI subcommands like `status`.
+/// Disabled by default, as any local user can connect to it and there is no authentication
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Control {
+    pub enabled: bool,
+    /// TCP port on 127.0.0.1
+    pub port: u16,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        return Self {
+            enabled: false,
+            port: 7766,
+        };
+    }
+}
+
+/// Rhai script transforming messages before they are sent to keyboard
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ScriptConfig {
+    pub enabled: bool,
+    /// Script is reloaded automatically when this file changes
+    pub path: String,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            path: "./qmk-hid-host.rhai".to_string(),
+        };
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Default)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Providers {
+    pub time: TimeProviderConfig,
+    pub layout: LayoutProviderConfig,
+    pub volume: VolumeProviderConfig,
+    pub microphone: MicrophoneProviderConfig,
+    pub media: MediaProviderConfig,
+    pub calendar: CalendarProviderConfig,
+    pub break_reminder: BreakReminderProviderConfig,
+    /// External commands, every entry runs as a separate provider
+    pub commands: Vec<CommandProviderConfig>,
+    pub plugins: PluginsConfig,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct TimeProviderConfig {
+    pub enabled: bool,
+    /// Maximum delay between clock checks, updates are aligned to second or minute boundary anyway
+    pub poll_interval: u64,
+    /// Send seconds every second
+    pub seconds: bool,
+    /// Send day, month and year when date changes
+    pub date: bool,
+    /// Send day of week when it changes
+    pub weekday: bool,
+    pub hour_format: HourFormat,
+    /// Send Unix timestamp and UTC offset on connect, every `clockSyncInterval` and after time zone changes
+    pub clock_sync: bool,
+    /// Interval between clock syncs in milliseconds
+    pub clock_sync_interval: u64,
+    /// Additional time zones, time in every zone is sent with its index in this list
+    pub zones: Vec<TimeZoneConfig>,
+    pub timer: TimerConfig,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TimeZoneConfig {
+    /// IANA time zone name like `America/New_York`
+    pub zone: String,
+    /// Short label sent along with the time, like `NYC`
+    pub label: String,
+}
+
+impl Default for TimeProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            poll_interval: 1000,
+            seconds: false,
+            date: false,
+            weekday: false,
+            hour_format: HourFormat::default(),
+            clock_sync: false,
+            clock_sync_interval: 3_600_000,
+            zones: vec![],
+            timer: TimerConfig::default(),
+        };
+    }
+}
+
+/// Pomodoro and countdown timer, all durations are in minutes
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct TimerConfig {
+    pub work_duration: u64,
+    pub break_duration: u64,
+    pub long_break_duration: u64,
+    /// Every n-th break is long, `0` disables long breaks
+    pub long_break_interval: u32,
+    /// Show desktop notification when a phase is finished
+    pub notifications: bool,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        return Self {
+            work_duration: 25,
+            break_duration: 5,
+            long_break_duration: 15,
+            long_break_interval: 4,
+            notifications: true,
+        };
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone, Copy, PartialEq, Default)]
+pub enum HourFormat {
+    #[default]
+    #[serde(rename = "24h")]
+    H24,
+    #[serde(rename = "12h")]
+    H12,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct LayoutProviderConfig {
+    pub enabled: bool,
+    pub poll_interval: u64,
+    /// Supported layouts in two-letter format, index of current layout is sent to keyboard
+    pub layouts: Vec<String>,
+}
+
+impl Default for LayoutProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            poll_interval: 100,
+            layouts: vec!["en".to_string(), "ru".to_string()],
+        };
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct VolumeProviderConfig {
+    pub enabled: bool,
+    /// Maximum length of output device name in bytes
+    pub max_length: usize,
+}
+
+impl Default for VolumeProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            max_length: 30,
+        };
+    }
+}
+
+/// Disabled by default, as not every keyboard has a place to show microphone state
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone, Default)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct MicrophoneProviderConfig {
+    pub enabled: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct MediaProviderConfig {
+    pub enabled: bool,
+    pub poll_interval: u64,
+    /// Player names to take media info from (case-insensitive substring match), empty list allows any player
+    pub players: Vec<String>,
+    /// Maximum length of artist and title in bytes
+    pub max_length: usize,
+}
+
+impl Default for MediaProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            poll_interval: 1000,
+            players: vec![],
+            max_length: 30,
+        };
+    }
+}
+
+impl MediaProviderConfig {
+    pub fn is_player_allowed(&self, player: &str) -> bool {
+        let player = player.to_lowercase();
+        return self.players.is_empty() || self.players.iter().any(|x| player.contains(&x.to_lowercase()));
+    }
+}
+
+/// Next event from local iCalendar files, started only if `paths` is not empty
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct CalendarProviderConfig {
+    pub enabled: bool,
+    /// `.ics` files or directories with them (searched recursively), like vdirsyncer or khal storage
+    pub paths: Vec<String>,
+    /// Delay between checking files for changes in milliseconds
+    pub poll_interval: u64,
+    /// Minutes before event start when alert message is sent
+    pub alert_lead_time: u64,
+    /// Maximum length of event title in bytes
+    pub max_length: usize,
+}
+
+impl Default for CalendarProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            paths: vec![],
+            poll_interval: 60000,
+            alert_lead_time: 5,
+            max_length: 30,
+        };
+    }
+}
+
+/// Reminds to take a break after long continuous activity, idle periods longer than `idleThreshold` count as breaks
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct BreakReminderProviderConfig {
+    pub enabled: bool,
+    pub poll_interval: u64,
+    /// Continuous activity in milliseconds after which reminder is sent
+    pub break_interval: u64,
+    /// Delay between repeated reminders until the break in milliseconds, `0` sends reminder only once
+    pub reminder_interval: u64,
+    /// Minimal idle time in milliseconds that counts as a break
+    pub idle_threshold: u64,
+}
+
+impl Default for BreakReminderProviderConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: false,
+            poll_interval: 5000,
+            break_interval: 3_000_000,
+            reminder_interval: 300_000,
+            idle_threshold: 300_000,
+        };
+    }
+}
+
+/// WebAssembly plugins, every `.wasm` file in `directory` runs as a separate provider
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    pub enabled: bool,
+    pub directory: String,
+    /// Files and directories each plugin is allowed to read, by plugin file name without extension
+    pub allowed_files: std::collections::BTreeMap<String, Vec<String>>,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        return Self {
+            enabled: true,
+            directory: "./plugins".to_string(),
+            allowed_files: std::collections::BTreeMap::new(),
+        };
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CommandMode {
+    /// Command is started every `interval`, every line of its output is sent
+    #[default]
+    Periodic,
+    /// Command is started once and every line it prints is sent immediately
+    Stream,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PayloadFormat {
+    /// UTF-8 text prefixed with its length, same as media messages
+    #[default]
+    Text,
+    /// Numbers separated by spaces or commas (`1 0x2F 255`), or JSON array of numbers
+    Bytes,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CommandProviderConfig {
+    /// Unique name used in logs and status
+    pub name: String,
+    /// Executable to run, use `sh -c` in `args` for shell features
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub mode: CommandMode,
+    /// Delay between runs in `periodic` mode in milliseconds
+    #[serde(default = "default_command_interval")]
+    pub interval: u64,
+    /// Time limit for one run in `periodic` mode in milliseconds, command is killed when it expires
+    #[serde(default = "default_command_timeout")]
+    pub timeout: u64,
+    /// Message type code sent as the first byte, must match firmware
+    pub message_type: u8,
+    #[serde(default)]
+    pub payload: PayloadFormat,
+    /// JSON Pointer (like `/build/status`), if set every line is parsed as JSON and the pointed value is sent
+    #[serde(default)]
+    pub json_pointer: Option<String>,
+    /// Maximum payload length in bytes
+    #[serde(default = "default_command_max_length")]
+    pub max_length: usize,
+}
+
+fn default_command_interval() -> u64 {
+    return 60000;
+}
+
+fn default_command_timeout() -> u64 {
+    return 30000;
+}
+
+fn default_command_max_length() -> usize {
+    return 30;
+}
+
+/// Accepts either a decimal number (`2116`) or a hex string (`"0x0844"`, `"0844"`), always writes a hex string.
+mod hex {
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        return schemars::json_schema!({
+            "anyOf": [
+                { "type": "integer", "minimum": 0, "maximum": 65535 },
+                { "type": "string", "pattern": "^(0[xX])?[0-9a-fA-F]{1,4}$" }
+            ]
+        });
+    }
+
+    pub fn serialize<S: Serializer>(value: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(&format!("0x{:04X}", value));
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+        return deserializer.deserialize_any(HexVisitor);
+    }
+
+    struct HexVisitor;
+
+    impl<'de> de::Visitor<'de> for HexVisitor {
+        type Value = u16;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a number or a hex string like \"0xFEED\"")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u16, E> {
+            return u16::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self));
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u16, E> {
+            return u16::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self));
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u16, E> {
+            let trimmed = value.trim();
+            let digits = trimmed.strip_prefix("0x").or_else(|| trimmed.strip_prefix("0X")).unwrap_or(trimmed);
+            return u16::from_str_radix(digits, 16).map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self));
+        }
+    }
 }
 
-pub fn get_config() -> Config {
+const CONFIG_PATH: &str = "./qmk-hid-host.json";
+const TOML_CONFIG_PATH: &str = "./qmk-hid-host.toml";
+const YAML_CONFIG_PATHS: [&str; 2] = ["./qmk-hid-host.yaml", "./qmk-hid-host.yml"];
+const SCHEMA_PATH: &str = "./qmk-hid-host.schema.json";
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+fn find_config_file() -> Option<(&'static str, Format)> {
+    let candidates = [(CONFIG_PATH, Format::Json), (TOML_CONFIG_PATH, Format::Toml)]
+        .into_iter()
+        .chain(YAML_CONFIG_PATHS.into_iter().map(|x| (x, Format::Yaml)));
+    let existing = candidates
+        .filter(|(path, _)| std::path::Path::new(path).exists())
+        .collect::<Vec<_>>();
+    if existing.len() > 1 {
+        tracing::warn!("Multiple config files found, using {}", existing[0].0);
+    }
+
+    return existing.into_iter().next();
+}
+
+fn parse_config(content: &str, format: Format) -> Result<Config, String> {
+    return match format {
+        Format::Json => serde_json::from_str::<Config>(content).map_err(|e| e.to_string()),
+        Format::Toml => toml::from_str::<Config>(content).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str::<Config>(content).map_err(|e| e.to_string()),
+    };
+}
+
+fn parse_value(content: &str, format: Format) -> Result<serde_json::Value, String> {
+    return match format {
+        Format::Json => serde_json::from_str::<serde_json::Value>(content).map_err(|e| e.to_string()),
+        Format::Toml => toml::from_str::<serde_json::Value>(content).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str::<serde_json::Value>(content).map_err(|e| e.to_string()),
+    };
+}
+
+fn serialize_config(config: &Config, format: Format) -> Result<String, String> {
+    return match format {
+        Format::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+        Format::Toml => toml::to_string_pretty(config).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(config).map_err(|e| e.to_string()),
+    };
+}
+
+fn get_backup_path(path: &str, version: u32) -> String {
+    return format!("{}.v{}.bak", path, version);
+}
+
+/// Parses config content and migrates it to the current version in memory, returns config and its original version
+fn parse_migrated_config(content: &str, format: Format) -> Result<(Config, u32), String> {
+    let mut value = parse_value(content, format)?;
+    let version = migration::get_version(&value);
+    if version == migration::CURRENT_VERSION {
+        return parse_config(content, format).map(|x| (x, version));
+    }
+
+    if version > migration::CURRENT_VERSION {
+        return Err(format!(
+            "config version {} is not supported, latest supported version is {}",
+            version,
+            migration::CURRENT_VERSION
+        ));
+    }
+
+    migration::migrate(&mut value, version);
+    let config = serde_json::from_value::<Config>(value).map_err(|e| format!("migrated config is invalid: {}", e))?;
+    return Ok((config, version));
+}
+
+/// Reads config file, migrating it to the current version if needed.
+/// JSON file is rewritten and the original is kept as a backup, TOML and YAML files are migrated in memory only to keep their comments
+fn read_config(path: &str, format: Format) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let (config, version) = parse_migrated_config(&content, format)?;
+    if version == migration::CURRENT_VERSION {
+        return Ok(config);
+    }
+
+    if !matches!(format, Format::Json) {
+        tracing::warn!(
+            "Config {} has version {} and was migrated in memory, update it to version {} manually (see README) to keep comments",
+            path,
+            version,
+            migration::CURRENT_VERSION
+        );
+        return Ok(config);
+    }
+
+    let backup_path = get_backup_path(path, version);
+    std::fs::copy(path, &backup_path).map_err(|e| format!("can not create backup {}: {}", backup_path, e))?;
+    std::fs::write(path, serialize_config(&config, format)?).map_err(|e| e.to_string())?;
+    tracing::info!(
+        "Config migrated from version {} to {}, original file saved to {}",
+        version,
+        migration::CURRENT_VERSION,
+        backup_path
+    );
+
+    return Ok(config);
+}
+
+/// Reads control server settings without writing anything, migration of old config is done in memory only
+fn read_control_config(path: &str, format: Format) -> Result<Control, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    return parse_migrated_config(&content, format).map(|(config, _)| config.control);
+}
+
+/// Control settings for command line client, defaults are used when there is no config file
+pub fn get_control_config() -> Result<Control, String> {
+    let Some((path, format)) = find_config_file() else {
+        return Ok(Control::default());
+    };
+
+    return read_control_config(path, format).map_err(|e| format!("Invalid config file {}: {}", path, e));
+}
+
+/// Checks constraints that can not be expressed by types
+fn validate_config(config: &Config) -> Result<(), String> {
+    for zone in &config.providers.time.zones {
+        zone.zone
+            .parse::<chrono_tz::Tz>()
+            .map_err(|_| format!("unknown time zone {}", zone.zone))?;
+    }
+
+    let mut names = std::collections::HashSet::new();
+    for command in &config.providers.commands {
+        if !names.insert(command.name.to_lowercase()) {
+            return Err(format!("command provider name {} is not unique", command.name));
+        }
+
+        if let Some(data_type) = crate::data_type::DataType::from_u8(command.message_type) {
+            return Err(format!(
+                "messageType {:#04X} of command {} is used by built-in {:?} message",
+                command.message_type, command.name, data_type
+            ));
+        }
+
+        // Text payload starts with its length byte
+        if command.max_length > u8::MAX as usize {
+            return Err(format!("maxLength of command {} must not exceed 255", command.name));
+        }
+    }
+
+    return Ok(());
+}
+
+pub fn get_schema() -> String {
+    return serde_json::to_string_pretty(&schemars::schema_for!(Config)).unwrap();
+}
+
+pub fn get_config() -> Option<Config> {
     let default_config = Config {
+        schema: Some(SCHEMA_PATH.to_string()),
+        version: migration::CURRENT_VERSION,
         device: Device {
             vendor_id: 0xe126,
             product_id: 0x0,
             usage: 0x61,
             usage_page: 0xff60,
+            reconnect_delay: default_reconnect_delay(),
+            heartbeat_interval: default_heartbeat_interval(),
         },
-        layouts: vec!["en".to_string(), "ru".to_string()],
-        reconnect_delay: 5000,
+        providers: Providers::default(),
+        control: Control::default(),
+        script: ScriptConfig::default(),
     };
 
-    if let Ok(file) = std::fs::read_to_string("./qmk-hid-host.json") {
-        if let Ok(file_config) = serde_json::from_str::<Config>(&file) {
-            tracing::info!("Read config from file");
-            return file_config;
-        }
-
-        tracing::error!("Error while reading config from file");
+    if let Some((path, format)) = find_config_file() {
+        return match read_config(path, format).and_then(|x| validate_config(&x).map(|_| x)) {
+            Ok(file_config) => {
+                tracing::info!("Read config from file {}", path);
+                Some(file_config)
+            }
+            Err(e) => {
+                tracing::error!("Invalid config file {}: {}", path, e);
+                None
+            }
+        };
     }
 
     let file_content = serde_json::to_string_pretty(&default_config).unwrap();
-    std::fs::write("./qmk-hid-host.json", &file_content).unwrap();
+    std::fs::write(CONFIG_PATH, &file_content).unwrap();
     tracing::info!("New config file created");
 
-    return default_config;
+    if !std::path::Path::new(SCHEMA_PATH).exists() {
+        std::fs::write(SCHEMA_PATH, get_schema()).unwrap_or_else(|e| tracing::error!("Can not write config schema: {}", e));
+    }
+
+    return Some(default_config);
+}
+
+/// Converts JSON config to TOML, existing TOML config is never overwritten
+pub fn convert_to_toml() -> Result<(), String> {
+    if std::path::Path::new(TOML_CONFIG_PATH).exists() {
+        return Err(format!("{} already exists", TOML_CONFIG_PATH));
+    }
+
+    let config = read_config(CONFIG_PATH, Format::Json).map_err(|e| format!("Invalid config file {}: {}", CONFIG_PATH, e))?;
+    let file_content = serialize_config(&config, Format::Toml)?;
+    std::fs::write(TOML_CONFIG_PATH, &file_content).map_err(|e| format!("Can not write {}: {}", TOML_CONFIG_PATH, e))?;
+    tracing::info!("Config converted to {}, {} can be removed now", TOML_CONFIG_PATH, CONFIG_PATH);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Deserialize)]
+    struct HexValue(#[serde(with = "hex")] u16);
+
+    fn parse_hex(json: &str) -> Result<u16, String> {
+        return serde_json::from_str::<HexValue>(json).map(|x| x.0).map_err(|e| e.to_string());
+    }
+
+    const V1_CONFIG: &str = r#"{ "device": { "productId": "0x0844", "usage": "0x61", "usagePage": "0xFF60" }, "layouts": ["en", "de"], "reconnectDelay": 1000 }"#;
+
+    /// Unique file in temp directory, removed with its backups when dropped
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("qmk-hid-host-{}-{}", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+            return Self(path.to_string_lossy().to_string());
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(get_backup_path(&self.0, 1));
+        }
+    }
+
+    #[test]
+    fn json_config_is_migrated_with_backup() {
+        let file = TempFile::new("migrate.json", V1_CONFIG);
+        let config = read_config(&file.0, Format::Json).unwrap();
+        assert_eq!(config.version, migration::CURRENT_VERSION);
+        assert_eq!(config.providers.layout.layouts, vec!["en", "de"]);
+        assert_eq!(config.device.reconnect_delay, 1000);
+
+        assert_eq!(std::fs::read_to_string(get_backup_path(&file.0, 1)).unwrap(), V1_CONFIG);
+        let rewritten = read_config(&file.0, Format::Json).unwrap();
+        assert_eq!(rewritten.version, migration::CURRENT_VERSION);
+        assert_eq!(rewritten.providers.layout.layouts, vec!["en", "de"]);
+    }
+
+    #[test]
+    fn control_config_is_read_without_migration() {
+        let file = TempFile::new("control.json", V1_CONFIG);
+        let control = read_control_config(&file.0, Format::Json).unwrap();
+        assert!(!control.enabled);
+
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap(), V1_CONFIG);
+        assert!(!std::path::Path::new(&get_backup_path(&file.0, 1)).exists());
+    }
+
+    #[test]
+    fn toml_config_is_migrated_in_memory() {
+        let content =
+            "# keyboard\nlayouts = [\"en\", \"de\"]\n\n[device]\nproductId = \"0x0844\"\nusage = \"0x61\"\nusagePage = \"0xFF60\"\n";
+        let file = TempFile::new("migrate.toml", content);
+        let config = read_config(&file.0, Format::Toml).unwrap();
+        assert_eq!(config.version, migration::CURRENT_VERSION);
+        assert_eq!(config.providers.layout.layouts, vec!["en", "de"]);
+
+        assert_eq!(std::fs::read_to_string(&file.0).unwrap(), content);
+        assert!(!std::path::Path::new(&get_backup_path(&file.0, 1)).exists());
+    }
+
+    #[test]
+    fn newer_config_version_is_rejected() {
+        let file = TempFile::new("newer.json", r#"{ "version": 100, "device": {} }"#);
+        assert!(read_config(&file.0, Format::Json).is_err());
+        assert!(!std::path::Path::new(&get_backup_path(&file.0, 100)).exists());
+    }
+
+    fn create_command(name: &str, message_type: u8) -> CommandProviderConfig {
+        return serde_json::from_value(serde_json::json!({ "name": name, "command": "true", "messageType": message_type })).unwrap();
+    }
+
+    fn validate_commands(commands: Vec<CommandProviderConfig>) -> Result<(), String> {
+        let device = serde_json::json!({ "productId": "0x0844", "usage": "0x61", "usagePage": "0xFF60" });
+        let mut config = serde_json::from_value::<Config>(serde_json::json!({ "version": 2, "device": device })).unwrap();
+        config.providers.commands = commands;
+        return validate_config(&config);
+    }
+
+    #[test]
+    fn command_message_type_must_not_be_built_in() {
+        assert!(validate_commands(vec![create_command("Build", 0xD0)]).is_ok());
+        assert!(validate_commands(vec![create_command("Build", 0xA9)]).is_ok());
+        assert!(validate_commands(vec![create_command("Build", 0xAA)]).is_err());
+        assert!(validate_commands(vec![create_command("Build", 0xBD)]).is_err());
+    }
+
+    #[test]
+    fn command_names_must_be_unique() {
+        assert!(validate_commands(vec![create_command("Build", 0xD0), create_command("build", 0xD1)]).is_err());
+        assert!(validate_commands(vec![create_command("Build", 0xD0), create_command("Deploy", 0xD1)]).is_ok());
+    }
+
+    #[test]
+    fn command_max_length_fits_byte() {
+        let mut command = create_command("Build", 0xD0);
+        command.max_length = 255;
+        assert!(validate_commands(vec![command.clone()]).is_ok());
+        command.max_length = 256;
+        assert!(validate_commands(vec![command]).is_err());
+    }
+
+    #[test]
+    fn hex_accepts_prefixed_string() {
+        assert_eq!(parse_hex(r#""0x4B42""#), Ok(0x4B42));
+        assert_eq!(parse_hex(r#""0X4b42""#), Ok(0x4B42));
+    }
+
+    #[test]
+    fn hex_accepts_string_without_prefix_in_any_case() {
+        assert_eq!(parse_hex(r#""4B42""#), Ok(0x4B42));
+        assert_eq!(parse_hex(r#""4b42""#), Ok(0x4B42));
+        assert_eq!(parse_hex(r#"" ff60 ""#), Ok(0xFF60));
+    }
+
+    #[test]
+    fn hex_accepts_decimal_number() {
+        assert_eq!(parse_hex("19266"), Ok(0x4B42));
+        assert_eq!(parse_hex("0"), Ok(0));
+        assert_eq!(parse_hex("65535"), Ok(0xFFFF));
+    }
+
+    #[test]
+    fn hex_rejects_overflow() {
+        assert!(parse_hex("65536").is_err());
+        assert!(parse_hex("-1").is_err());
+        assert!(parse_hex(r#""0x10000""#).is_err());
+    }
+
+    #[test]
+    fn hex_rejects_garbage() {
+        assert!(parse_hex(r#""""#).is_err());
+        assert!(parse_hex(r#""0x""#).is_err());
+        assert!(parse_hex(r#""0xZZ""#).is_err());
+        assert!(parse_hex(r#""12 34""#).is_err());
+        assert!(parse_hex("1.5").is_err());
+        assert!(parse_hex("true").is_err());
+    }
+
+    #[test]
+    fn hex_serializes_as_prefixed_upper_case() {
+        let value = hex::serialize(&0x4b42, serde_json::value::Serializer).unwrap();
+        assert_eq!(value, serde_json::json!("0x4B42"));
+    }
 }
@@ -0,0 +1,98 @@
+use serde_json::{Map, Value};
+
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Each migration upgrades config from version `index + 1` to `index + 2`
+const MIGRATIONS: [fn(&mut Map<String, Value>); 1] = [migrate_v1];
+
+/// Files without `version` field were created before versioning was introduced
+pub fn get_version(config: &Value) -> u32 {
+    return config.get("version").and_then(|x| x.as_u64()).map(|x| x as u32).unwrap_or(1);
+}
+
+pub fn migrate(config: &mut Value, version: u32) {
+    let Some(root) = config.as_object_mut() else {
+        return;
+    };
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version.saturating_sub(1) as usize) {
+        tracing::debug!("Migrating config to version {}", index + 2);
+        migration(root);
+    }
+
+    root.insert("version".to_string(), Value::from(CURRENT_VERSION));
+}
+
+fn get_section<'a>(root: &'a mut Map<String, Value>, key: &str) -> &'a mut Map<String, Value> {
+    let section = root.entry(key).or_insert_with(|| Value::Object(Map::new()));
+    if !section.is_object() {
+        *section = Value::Object(Map::new());
+    }
+
+    return section.as_object_mut().unwrap();
+}
+
+/// v1 kept `layouts` and `reconnectDelay` in the root, v2 moves them to `providers.layout` and `device`
+fn migrate_v1(root: &mut Map<String, Value>) {
+    if let Some(layouts) = root.remove("layouts") {
+        let providers = get_section(root, "providers");
+        get_section(providers, "layout").insert("layouts".to_string(), layouts);
+    }
+
+    if let Some(reconnect_delay) = root.remove("reconnectDelay") {
+        get_section(root, "device").insert("reconnectDelay".to_string(), reconnect_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn version_defaults_to_1() {
+        assert_eq!(get_version(&json!({})), 1);
+        assert_eq!(get_version(&json!({ "version": "2" })), 1);
+        assert_eq!(get_version(&json!({ "version": 1.5 })), 1);
+        assert_eq!(get_version(&json!({ "version": -2 })), 1);
+        assert_eq!(get_version(&json!({ "version": 2 })), 2);
+    }
+
+    #[test]
+    fn v1_moves_layouts_to_layout_provider() {
+        let mut config = json!({ "layouts": ["en", "de"], "providers": { "layout": { "pollInterval": 200 } } });
+        migrate(&mut config, 1);
+        assert_eq!(
+            config,
+            json!({ "version": CURRENT_VERSION, "providers": { "layout": { "pollInterval": 200, "layouts": ["en", "de"] } } })
+        );
+    }
+
+    #[test]
+    fn v1_moves_reconnect_delay_to_device() {
+        let mut config = json!({ "reconnectDelay": 1000, "device": { "productId": "0x0844" } });
+        migrate(&mut config, 1);
+        assert_eq!(
+            config,
+            json!({ "version": CURRENT_VERSION, "device": { "productId": "0x0844", "reconnectDelay": 1000 } })
+        );
+    }
+
+    #[test]
+    fn v1_replaces_invalid_sections() {
+        let mut config = json!({ "layouts": ["en"], "providers": 5 });
+        migrate(&mut config, 1);
+        assert_eq!(
+            config,
+            json!({ "version": CURRENT_VERSION, "providers": { "layout": { "layouts": ["en"] } } })
+        );
+    }
+
+    #[test]
+    fn current_version_is_not_changed() {
+        let mut config = json!({ "version": CURRENT_VERSION, "layouts": ["en"] });
+        migrate(&mut config, CURRENT_VERSION);
+        assert_eq!(config, json!({ "version": CURRENT_VERSION, "layouts": ["en"] }));
+    }
+}
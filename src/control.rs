@@ -0,0 +1,86 @@
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{supervisor::Health, timer::Timer};
+
+/// Line-based control server on localhost, every command gets a single line response
+pub struct ControlServer {
+    port: u16,
+    health: Health,
+    timer: Timer,
+}
+
+impl ControlServer {
+    pub fn new(port: u16, health: Health, timer: Timer) -> Self {
+        return Self { port, health, timer };
+    }
+
+    fn handle_command(&self, command: &str) -> String {
+        let (name, args) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+        return match name {
+            "status" => serde_json::to_string(&self.health.snapshot()).unwrap_or_else(|e| format!("error: {}", e)),
+            "timer" => self.timer.handle_command(args).unwrap_or_else(|e| format!("error: {}", e)),
+            "" => "error: empty command".to_string(),
+            _ => format!("error: unknown command {}", command.trim()),
+        };
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = self.handle_command(&line);
+            writer.write_all(format!("{}\n", response).as_bytes()).await?;
+        }
+
+        return Ok(());
+    }
+
+    pub async fn run(self) {
+        let listener = match TcpListener::bind(("127.0.0.1", self.port)).await {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Can not start control server on port {}: {}", self.port, e);
+                return;
+            }
+        };
+
+        tracing::info!("Control server listening on 127.0.0.1:{}", self.port);
+        let server = std::sync::Arc::new(self);
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let server = server.clone();
+                    tokio::spawn(async move {
+                        server
+                            .handle_connection(stream)
+                            .await
+                            .unwrap_or_else(|e| tracing::debug!("Control connection closed: {}", e));
+                    });
+                }
+                Err(e) => tracing::error!("Control server accept failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Sends a single command to the running instance and returns its response
+pub async fn send_command(port: u16, command: &str) -> Result<String, String> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.map_err(|e| {
+        format!(
+            "Can not connect to port {}, is qmk-hid-host running with `control.enabled` set to `true`? {}",
+            port, e
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", command).as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await.map_err(|e| e.to_string())?;
+    return Ok(response.trim_end().to_string());
+}
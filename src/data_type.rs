@@ -1,7 +1,53 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum DataType {
     Time = 0xAA, // random value that does not conflict with VIA/VIAL, must match firmware
     Volume,
     Layout,
     MediaArtist,
     MediaTitle,
+    HostDisconnected, // sent before app exits, firmware can show its default screen
+    Heartbeat,        // sent periodically and in response to the same message from keyboard
+    TimeSeconds,
+    Date,
+    Weekday,
+    ClockSync,
+    ZoneTime,
+    CalendarEvent,
+    CalendarAlert,
+    Timer, // also sent by keyboard to control the timer
+    TimerAlert,
+    BreakReminder,
+    SessionLength, // also sent by keyboard to request current session length
+    Microphone,    // also sent by keyboard to toggle mute
+    OutputDevice,
+}
+
+impl DataType {
+    /// Every built-in type, their codes can not be used by command providers
+    pub const ALL: [DataType; 20] = [
+        DataType::Time,
+        DataType::Volume,
+        DataType::Layout,
+        DataType::MediaArtist,
+        DataType::MediaTitle,
+        DataType::HostDisconnected,
+        DataType::Heartbeat,
+        DataType::TimeSeconds,
+        DataType::Date,
+        DataType::Weekday,
+        DataType::ClockSync,
+        DataType::ZoneTime,
+        DataType::CalendarEvent,
+        DataType::CalendarAlert,
+        DataType::Timer,
+        DataType::TimerAlert,
+        DataType::BreakReminder,
+        DataType::SessionLength,
+        DataType::Microphone,
+        DataType::OutputDevice,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<DataType> {
+        return DataType::ALL.into_iter().find(|x| *x as u8 == value);
+    }
 }
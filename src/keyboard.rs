@@ -1,7 +1,19 @@
+use std::time::{Duration, Instant};
+
 use hidapi::{HidApi, HidDevice, HidError};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, error::TryRecvError},
+};
+
+use crate::{config::Device, data_type::DataType};
 
-use crate::config::Device;
+pub const REPORT_LENGTH: usize = 32;
+/// Also limits latency of outgoing messages, as reading and writing share the same thread
+const READ_TIMEOUT: i32 = 10;
+/// Response of VIA-enabled firmware to messages it did not handle, such reports are not forwarded to providers
+const ID_UNHANDLED: u8 = 0xFF;
+const CLOSED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct Keyboard {
     vendor_id: u16,
@@ -9,16 +21,20 @@ pub struct Keyboard {
     usage: u16,
     usage_page: u16,
     reconnect_delay: u64,
+    heartbeat_interval: Option<Duration>,
+    thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl Keyboard {
-    pub fn new(device: Device, reconnect_delay: u64) -> Self {
+    pub fn new(device: Device) -> Self {
         return Self {
             vendor_id: device.vendor_id,
             product_id: device.product_id,
             usage: device.usage,
             usage_page: device.usage_page,
-            reconnect_delay,
+            reconnect_delay: device.reconnect_delay,
+            heartbeat_interval: Some(Duration::from_millis(device.heartbeat_interval)).filter(|x| !x.is_zero()),
+            thread: None,
         };
     }
 
@@ -39,30 +55,80 @@ impl Keyboard {
         return Err(HidError::HidApiErrorEmpty);
     }
 
-    pub fn connect(&self) -> (broadcast::Sender<bool>, mpsc::Sender<Vec<u8>>) {
+    fn write(device: &HidDevice, mut data: Vec<u8>) -> Result<usize, HidError> {
+        tracing::info!("Sending to keyboard: {:?}", data);
+        data.truncate(REPORT_LENGTH);
+        data.insert(0, 0);
+        return device.write(data.as_mut());
+    }
+
+    /// Heartbeat payload is its interval in seconds, firmware can treat host as gone after missing a few of them
+    fn get_heartbeat(interval: Option<Duration>) -> Vec<u8> {
+        let seconds = interval.map_or(0, |x| x.as_secs_f64().ceil().clamp(1.0, u8::MAX as f64) as u8);
+        return vec![DataType::Heartbeat as u8, seconds];
+    }
+
+    /// Returns connection events, sender for messages to keyboard and sender used to broadcast messages from keyboard.
+    /// Keyboard is closed after all senders for messages to keyboard are dropped and queued messages are sent
+    pub fn connect(&mut self) -> (broadcast::Receiver<bool>, mpsc::Sender<Vec<u8>>, broadcast::Sender<Vec<u8>>) {
         let vid = self.vendor_id;
         let pid = self.product_id;
         let usage = self.usage;
         let usage_page = self.usage_page;
         let reconnect_delay = self.reconnect_delay;
+        let heartbeat_interval = self.heartbeat_interval;
         let (data_sender, mut data_receiver) = mpsc::channel::<Vec<u8>>(32);
-        let (connected_sender, _) = broadcast::channel::<bool>(32);
-        let internal_connected_sender = connected_sender.clone();
-        std::thread::spawn(move || {
+        let (connected_sender, connected_receiver) = broadcast::channel::<bool>(32);
+        let (keyboard_sender, _) = broadcast::channel::<Vec<u8>>(32);
+        let received_sender = keyboard_sender.clone();
+        let thread = std::thread::spawn(move || {
             tracing::info!("Waiting for keyboard...");
-            loop {
+            while !data_receiver.is_closed() {
                 tracing::debug!("Trying to connect...");
                 if let Ok(device) = Self::get_device(&vid, &pid, &usage, &usage_page) {
-                    let _ = &internal_connected_sender.send(true).unwrap();
+                    let _ = connected_sender.send(true);
                     tracing::info!("Connected to keyboard");
+                    let mut last_heartbeat: Option<Instant> = None;
+                    let mut ping_received = false;
                     loop {
-                        let msg = data_receiver.blocking_recv();
-                        if let Some(mut received) = msg {
-                            tracing::info!("Sending to keyboard: {:?}", received);
-                            received.truncate(32);
-                            received.insert(0, 0);
-                            if let Err(_) = device.write(received.as_mut()) {
-                                let _ = internal_connected_sender.send(false).unwrap();
+                        let mut outgoing = match data_receiver.try_recv() {
+                            Ok(received) => Some(received),
+                            Err(TryRecvError::Empty) => None,
+                            Err(TryRecvError::Disconnected) => {
+                                tracing::info!("Keyboard closed");
+                                return;
+                            }
+                        };
+
+                        let heartbeat_due = heartbeat_interval.is_some_and(|x| last_heartbeat.is_none_or(|t| t.elapsed() >= x));
+                        if outgoing.is_none() && (heartbeat_due || ping_received) {
+                            outgoing = Some(Self::get_heartbeat(heartbeat_interval));
+                            last_heartbeat = Some(Instant::now());
+                            ping_received = false;
+                        }
+
+                        if let Some(data) = outgoing {
+                            if let Err(_) = Self::write(&device, data) {
+                                let _ = connected_sender.send(false);
+                                tracing::warn!("Disconnected from keyboard");
+
+                                break;
+                            }
+
+                            continue;
+                        }
+
+                        let mut buffer = [0u8; REPORT_LENGTH];
+                        match device.read_timeout(&mut buffer, READ_TIMEOUT) {
+                            Ok(0) => (),
+                            Ok(_) if buffer[0] == ID_UNHANDLED => tracing::debug!("Keyboard did not handle message: {:?}", buffer),
+                            Ok(_) if buffer[0] == DataType::Heartbeat as u8 => ping_received = true,
+                            Ok(length) => {
+                                tracing::info!("Received from keyboard: {:?}", &buffer[..length]);
+                                let _ = received_sender.send(buffer[..length].to_vec());
+                            }
+                            Err(_) => {
+                                let _ = connected_sender.send(false);
                                 tracing::warn!("Disconnected from keyboard");
 
                                 break;
@@ -71,10 +137,32 @@ impl Keyboard {
                     }
                 }
 
-                std::thread::sleep(std::time::Duration::from_millis(reconnect_delay));
+                let reconnect_at = Instant::now() + Duration::from_millis(reconnect_delay);
+                while Instant::now() < reconnect_at && !data_receiver.is_closed() {
+                    std::thread::sleep(CLOSED_CHECK_INTERVAL);
+                }
             }
         });
 
-        return (connected_sender, data_sender);
+        self.thread = Some(thread);
+        return (connected_receiver, data_sender, keyboard_sender);
+    }
+
+    /// Waits until keyboard is closed, returns `false` if it did not happen in time
+    pub async fn closed(&mut self, timeout: Duration) -> bool {
+        let Some(thread) = self.thread.take() else {
+            return true;
+        };
+
+        let deadline = Instant::now() + timeout;
+        while !thread.is_finished() {
+            if Instant::now() > deadline {
+                return false;
+            }
+
+            tokio::time::sleep(CLOSED_CHECK_INTERVAL).await;
+        }
+
+        return thread.join().is_ok();
     }
 }
@@ -4,45 +4,208 @@
 )]
 
 mod config;
+mod control;
 mod data_type;
 mod keyboard;
 mod providers;
+mod script;
+mod supervisor;
+mod timer;
+
+use std::time::Duration;
 
 use config::get_config;
+use control::ControlServer;
+use data_type::DataType;
 use keyboard::Keyboard;
+use script::Script;
+use supervisor::Supervisor;
+use timer::Timer;
+use tokio_util::sync::CancellationToken;
+
+use providers::{
+    _base::Provider, break_reminder::BreakReminderProvider, calendar::CalendarProvider, command::CommandProvider, layout::LayoutProvider,
+    media::MediaProvider, microphone::MicrophoneProvider, plugin, time::TimeProvider, volume::VolumeProvider,
+};
+
+/// Time to send queued messages to keyboard after providers are stopped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Completes on Ctrl+C, termination or logout
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+        _ = hangup.recv() => (),
+    }
+}
+
+/// Completes on Ctrl+C, console close, logoff or system shutdown
+#[cfg(windows)]
+async fn shutdown_signal() {
+    use tokio::signal::windows::{ctrl_close, ctrl_logoff, ctrl_shutdown};
 
-use providers::{_base::Provider, layout::LayoutProvider, time::TimeProvider, volume::VolumeProvider, media::MediaProvider};
+    let mut close = ctrl_close().unwrap();
+    let mut logoff = ctrl_logoff().unwrap();
+    let mut shutdown = ctrl_shutdown().unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = close.recv() => (),
+        _ = logoff.recv() => (),
+        _ = shutdown.recv() => (),
+    }
+}
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let env_filter = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
         .from_env_lossy();
     let tracing_subscriber = tracing_subscriber::fmt().with_env_filter(env_filter).finish();
     let _ = tracing::subscriber::set_global_default(tracing_subscriber);
 
-    let config = get_config();
+    match std::env::args().nth(1).as_deref() {
+        Some("schema") => {
+            println!("{}", config::get_schema());
+            return;
+        }
+        Some("to-toml") => {
+            if let Err(e) = config::convert_to_toml() {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
 
-    let keyboard = Keyboard::new(config.device, config.reconnect_delay);
-    let (connected_sender, data_sender) = keyboard.connect();
+            return;
+        }
+        _ => (),
+    }
 
-    let providers: Vec<Box<dyn Provider>> = vec![
-        TimeProvider::new(data_sender.clone(), connected_sender.clone()),
-        LayoutProvider::new(data_sender.clone(), connected_sender.clone(), config.layouts),
-        VolumeProvider::new(data_sender.clone(), connected_sender.clone()),
-        MediaProvider::new(data_sender.clone(), connected_sender.clone()),
-    ];
+    // Client commands only read control settings, config file is not created or migrated
+    let control_command = match std::env::args().nth(1).as_deref() {
+        Some("status") => Some("status".to_string()),
+        Some("timer") => Some(std::env::args().skip(1).collect::<Vec<_>>().join(" ")),
+        _ => None,
+    };
 
-    
-    let mut is_connected = false;
-    let mut connected_receiver = connected_sender.subscribe();
+    if let Some(command) = control_command {
+        let control = config::get_control_config().unwrap_or_else(|e| {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        });
+        if !control.enabled {
+            tracing::error!("Control server is disabled, set `control.enabled` to `true` in config to use this command");
+            std::process::exit(1);
+        }
 
-    loop {
-        if let Ok(connected) = connected_receiver.blocking_recv() {
-            if !is_connected && connected {
-                providers.iter().for_each(|p| p.start());
+        match control::send_command(control.port, &command).await {
+            Ok(response) if response.starts_with("error:") => {
+                tracing::error!("{}", response);
+                std::process::exit(1);
             }
+            Ok(response) => println!("{}", response),
+            Err(e) => {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let Some(config) = get_config() else {
+        std::process::exit(1);
+    };
+
+    let mut keyboard = Keyboard::new(config.device);
+    let (connected_receiver, keyboard_data_sender, keyboard_sender) = keyboard.connect();
+    let (data_sender, script_task) = if config.script.enabled {
+        let (data_sender, task) = Script::new(config.script, &config.providers.commands).connect(keyboard_data_sender.clone());
+        (data_sender, Some(task))
+    } else {
+        (keyboard_data_sender.clone(), None)
+    };
+
+    let timer = Timer::new(config.providers.time.timer.clone());
+    tokio::spawn(timer.clone().run());
+    let mut providers: Vec<Box<dyn Provider>> = vec![];
+
+    if config.providers.time.enabled {
+        providers.push(TimeProvider::new(config.providers.time, timer.clone()));
+    }
 
-            is_connected = connected;
+    if config.providers.layout.enabled {
+        providers.push(LayoutProvider::new(config.providers.layout));
+    }
+
+    if config.providers.volume.enabled {
+        providers.push(VolumeProvider::new(config.providers.volume));
+    }
+
+    if config.providers.microphone.enabled {
+        providers.push(MicrophoneProvider::new());
+    }
+
+    if config.providers.media.enabled {
+        providers.push(MediaProvider::new(config.providers.media));
+    }
+
+    if config.providers.calendar.enabled && !config.providers.calendar.paths.is_empty() {
+        providers.push(CalendarProvider::new(config.providers.calendar));
+    }
+
+    if config.providers.break_reminder.enabled {
+        providers.push(BreakReminderProvider::new(config.providers.break_reminder));
+    }
+
+    for command in config.providers.commands {
+        providers.push(CommandProvider::new(command));
+    }
+
+    if config.providers.plugins.enabled {
+        providers.extend(plugin::load_plugins(&config.providers.plugins));
+    }
+
+    let supervisor = match Supervisor::new(providers, data_sender, keyboard_sender) {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!("Invalid providers: {}, rename the command or plugin", e);
+            std::process::exit(1);
+        }
+    };
+    if config.control.enabled {
+        tokio::spawn(ControlServer::new(config.control.port, supervisor.health(), timer).run());
+    }
+
+    let shutdown = CancellationToken::new();
+    let signal_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down...");
+        signal_shutdown.cancel();
+    });
+
+    supervisor.run(connected_receiver, shutdown.clone()).await;
+    if !shutdown.is_cancelled() {
+        tracing::error!("Keyboard thread stopped unexpectedly");
+        std::process::exit(1);
+    }
+
+    // Messages queued in script are passed on first, final message bypasses script so it can not be dropped or changed
+    if let Some(task) = script_task {
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, task).await.is_err() {
+            tracing::warn!("Script did not finish in {:?}", SHUTDOWN_TIMEOUT);
         }
     }
+
+    let _ = keyboard_data_sender.try_send(vec![DataType::HostDisconnected as u8]);
+    drop(keyboard_data_sender);
+    if !keyboard.closed(SHUTDOWN_TIMEOUT).await {
+        tracing::error!("Keyboard was not closed in {:?}", SHUTDOWN_TIMEOUT);
+        std::process::exit(1);
+    }
 }
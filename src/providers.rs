@@ -1,6 +1,15 @@
 pub mod _base;
+
+#[cfg(target_os = "linux")]
+pub mod _audio;
+
+pub mod break_reminder;
+pub mod calendar;
+pub mod command;
 pub mod layout;
 pub mod media;
+pub mod microphone;
+pub mod plugin;
 pub mod time;
 
 pub mod volume;
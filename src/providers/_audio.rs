@@ -0,0 +1,11 @@
+pub mod pulse;
+
+#[cfg(feature = "pipewire")]
+pub mod pipewire;
+
+/// Default device watched by volume (sink) and microphone (source) providers
+#[derive(Clone, Copy)]
+pub enum DeviceKind {
+    Sink,
+    Source,
+}
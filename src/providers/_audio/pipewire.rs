@@ -0,0 +1,327 @@
+use std::{cell::RefCell, collections::HashMap, io::Cursor, rc::Rc, time::Duration};
+
+use pipewire::{
+    context::Context,
+    core::PW_ID_CORE,
+    main_loop::MainLoop,
+    metadata::{Metadata, MetadataListener},
+    node::{Node, NodeListener},
+    registry::{GlobalObject, Registry},
+    spa::{
+        param::ParamType,
+        pod::{deserialize::PodDeserializer, serialize::PodSerializer, Object, Pod, Property, Value, ValueArray},
+        sys::{SPA_PROP_channelVolumes, SPA_PROP_mute},
+        utils::{dict::DictRef, SpaTypes},
+    },
+    types::ObjectType,
+};
+
+use super::super::_base::{ProviderContext, ProviderResult};
+use super::DeviceKind;
+
+/// Loop is iterated with timeout to check for cancellation and keyboard commands between events
+const LOOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+type OnChange = Rc<RefCell<dyn FnMut(Option<&AudioNode>)>>;
+
+fn get_media_class(kind: DeviceKind) -> &'static str {
+    return match kind {
+        DeviceKind::Sink => "Audio/Sink",
+        DeviceKind::Source => "Audio/Source",
+    };
+}
+
+/// Metadata key holding default device as `{"name": "<node.name>"}`
+fn get_default_key(kind: DeviceKind) -> &'static str {
+    return match kind {
+        DeviceKind::Sink => "default.audio.sink",
+        DeviceKind::Source => "default.audio.source",
+    };
+}
+
+/// Sink or source node
+pub struct AudioNode {
+    // Listener must be removed before its proxy is destroyed
+    _listener: NodeListener,
+    proxy: Node,
+    pub name: String,
+    pub description: Option<String>,
+    /// Taken from the node or from its device
+    pub form_factor: Option<String>,
+    /// Linear volumes of all channels, shown volume is their cubic root
+    channel_volumes: Vec<f32>,
+    muted: bool,
+}
+
+/// Scales channel volumes so the loudest one is `volume` and balance is kept, like `pa_cvolume_scale`
+fn scale_volumes(volumes: &[f32], volume: f32) -> Vec<f32> {
+    let max = volumes.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return vec![volume; volumes.len().max(1)];
+    }
+
+    return volumes.iter().map(|x| x * volume / max).collect();
+}
+
+impl AudioNode {
+    pub fn get_volume(&self) -> (f32, bool) {
+        let volume = self.channel_volumes.first().map(|x| x.cbrt()).unwrap_or_default();
+        return (volume, self.muted);
+    }
+
+    /// New state is received by param listener
+    fn set_props(&self, property: Property) -> Result<(), String> {
+        let value = Value::Object(Object {
+            type_: SpaTypes::ObjectParamProps.as_raw(),
+            id: ParamType::Props.as_raw(),
+            properties: vec![property],
+        });
+        let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &value).map_err(|e| format!("{:?}", e))?;
+        let bytes = cursor.into_inner();
+        let pod = Pod::from_bytes(&bytes).ok_or("invalid pod")?;
+        self.proxy.set_param(ParamType::Props, 0, pod);
+        return Ok(());
+    }
+
+    pub fn set_mute(&self, mute: bool) -> Result<(), String> {
+        return self.set_props(Property::new(SPA_PROP_mute, Value::Bool(mute)));
+    }
+
+    /// `volume` is shown volume from `0.0` to `1.0`
+    pub fn set_volume(&self, volume: f32) -> Result<(), String> {
+        let volumes = scale_volumes(&self.channel_volumes, volume.powi(3));
+        return self.set_props(Property::new(
+            SPA_PROP_channelVolumes,
+            Value::ValueArray(ValueArray::Float(volumes)),
+        ));
+    }
+}
+
+#[derive(Default)]
+struct State {
+    nodes: HashMap<u32, AudioNode>,
+    /// Form factors of devices, nodes refer to them by `device.id`
+    devices: HashMap<u32, String>,
+    default_node: Option<String>,
+    /// Nothing is reported until initial globals are received
+    ready: bool,
+}
+
+impl State {
+    fn get_default_node(&self) -> Option<&AudioNode> {
+        let name = self.default_node.as_ref()?;
+        return self.nodes.values().find(|x| &x.name == name);
+    }
+
+    fn notify(&self, on_change: &OnChange) {
+        if self.ready {
+            (on_change.borrow_mut())(self.get_default_node());
+        }
+    }
+}
+
+fn get_prop(global: &GlobalObject<&DictRef>, key: &str) -> Option<String> {
+    return global.props.and_then(|x| x.get(key)).map(|x| x.to_string());
+}
+
+/// Default device metadata value is JSON, e.g. `{"name": "alsa_output.pci-0000_00_1f.3.analog-stereo"}`
+fn parse_default_node(value: &str) -> Option<String> {
+    let value = serde_json::from_str::<serde_json::Value>(value).ok()?;
+    return value.get("name")?.as_str().map(|x| x.to_string());
+}
+
+/// Updates node volume and mute from its `Props` param, other properties are ignored
+fn update_props(node: &mut AudioNode, pod: &Pod) {
+    let Ok((_, Value::Object(object))) = PodDeserializer::deserialize_from::<Value>(pod.as_bytes()) else {
+        return;
+    };
+
+    for property in object.properties {
+        match (property.key, property.value) {
+            (SPA_PROP_channelVolumes, Value::ValueArray(ValueArray::Float(volumes))) => node.channel_volumes = volumes,
+            (SPA_PROP_mute, Value::Bool(muted)) => node.muted = muted,
+            _ => (),
+        }
+    }
+}
+
+fn add_node(registry: &Registry, global: &GlobalObject<&DictRef>, state: &Rc<RefCell<State>>, on_change: &OnChange) {
+    let proxy = match registry.bind::<Node, _>(global) {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            tracing::warn!("Can not bind PipeWire node {}: {}", global.id, e);
+            return;
+        }
+    };
+
+    let id = global.id;
+    let weak_state = Rc::downgrade(state);
+    let param_on_change = on_change.clone();
+    let listener = proxy
+        .add_listener_local()
+        .param(move |_, _, _, _, pod| {
+            let (Some(state), Some(pod)) = (weak_state.upgrade(), pod) else {
+                return;
+            };
+
+            let mut state = state.borrow_mut();
+            if let Some(node) = state.nodes.get_mut(&id) {
+                update_props(node, pod);
+                state.notify(&param_on_change);
+            }
+        })
+        .register();
+    proxy.subscribe_params(&[ParamType::Props]);
+
+    // Devices are announced before their nodes
+    let mut state = state.borrow_mut();
+    let device_id = get_prop(global, "device.id").and_then(|x| x.parse::<u32>().ok());
+    let form_factor = get_prop(global, "device.form-factor").or_else(|| state.devices.get(&device_id?).cloned());
+    let node = AudioNode {
+        _listener: listener,
+        proxy,
+        name: get_prop(global, "node.name").unwrap_or_default(),
+        description: get_prop(global, "node.description"),
+        form_factor,
+        channel_volumes: Vec::new(),
+        muted: false,
+    };
+    state.nodes.insert(id, node);
+}
+
+/// Default device is taken from `default` metadata object, the same one used by `wpctl` and desktop environments
+fn bind_metadata(
+    registry: &Registry,
+    global: &GlobalObject<&DictRef>,
+    kind: DeviceKind,
+    state: &Rc<RefCell<State>>,
+    on_change: &OnChange,
+) -> Option<(MetadataListener, Metadata)> {
+    let metadata = registry
+        .bind::<Metadata, _>(global)
+        .map_err(|e| tracing::warn!("Can not bind PipeWire metadata: {}", e))
+        .ok()?;
+
+    let weak_state = Rc::downgrade(state);
+    let on_change = on_change.clone();
+    let listener = metadata
+        .add_listener_local()
+        .property(move |_, key, _, value| {
+            // Key is not set when all properties are removed
+            if key.is_none_or(|x| x == get_default_key(kind)) {
+                if let Some(state) = weak_state.upgrade() {
+                    let mut state = state.borrow_mut();
+                    state.default_node = value.and_then(parse_default_node);
+                    state.notify(&on_change);
+                }
+            }
+
+            return 0;
+        })
+        .register();
+    return Some((listener, metadata));
+}
+
+/// Watches default sink or source through PipeWire registry and metadata until cancelled, used when there is no PulseAudio server.
+/// `on_change` is called with the default node after initial globals are received and after changes,
+/// `on_keyboard` handles keyboard messages with the current default node
+pub fn watch_default_node(
+    mut ctx: ProviderContext,
+    kind: DeviceKind,
+    on_change: impl FnMut(Option<&AudioNode>) + 'static,
+    mut on_keyboard: impl FnMut(Option<&AudioNode>, &[u8]),
+) -> ProviderResult {
+    let mainloop = MainLoop::new(None).map_err(|e| format!("Can not create PipeWire mainloop: {}", e))?;
+    let context = Context::new(&mainloop).map_err(|e| format!("Can not create PipeWire context: {}", e))?;
+    let core = context.connect(None).map_err(|e| format!("Can not connect to PipeWire: {}", e))?;
+    let registry = Rc::new(core.get_registry().map_err(|e| format!("Can not get PipeWire registry: {}", e))?);
+
+    let on_change: OnChange = Rc::new(RefCell::new(on_change));
+    let state = Rc::new(RefCell::new(State::default()));
+    let metadata = Rc::new(RefCell::new(None));
+    let error = Rc::new(RefCell::new(None));
+
+    let _core_listener = core
+        .add_listener_local()
+        .done({
+            let state = state.clone();
+            let on_change = on_change.clone();
+            move |id, _| {
+                let mut state = state.borrow_mut();
+                if id == PW_ID_CORE && !state.ready {
+                    state.ready = true;
+                    state.notify(&on_change);
+                }
+            }
+        })
+        .error({
+            let error = error.clone();
+            move |id, _, res, message| {
+                tracing::warn!("PipeWire error on object {}: {} ({})", id, message, res);
+                if id == PW_ID_CORE {
+                    *error.borrow_mut() = Some(message.to_string());
+                }
+            }
+        })
+        .register();
+
+    let weak_registry = Rc::downgrade(&registry);
+    let removed_state = Rc::downgrade(&state);
+    let removed_on_change = on_change.clone();
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let state = state.clone();
+            let metadata = metadata.clone();
+            let on_change = on_change.clone();
+            move |global| {
+                let Some(registry) = weak_registry.upgrade() else {
+                    return;
+                };
+
+                match global.type_ {
+                    ObjectType::Node if get_prop(global, "media.class").as_deref() == Some(get_media_class(kind)) => {
+                        add_node(&registry, global, &state, &on_change);
+                    }
+                    ObjectType::Device => {
+                        if let Some(form_factor) = get_prop(global, "device.form-factor") {
+                            state.borrow_mut().devices.insert(global.id, form_factor);
+                        }
+                    }
+                    ObjectType::Metadata if get_prop(global, "metadata.name").as_deref() == Some("default") => {
+                        *metadata.borrow_mut() = bind_metadata(&registry, global, kind, &state, &on_change);
+                    }
+                    _ => (),
+                }
+            }
+        })
+        .global_remove(move |id| {
+            if let Some(state) = removed_state.upgrade() {
+                let mut state = state.borrow_mut();
+                state.devices.remove(&id);
+                if state.nodes.remove(&id).is_some() {
+                    state.notify(&removed_on_change);
+                }
+            }
+        })
+        .register();
+
+    // Reply to sync comes after all existing globals are announced
+    core.sync(0).map_err(|e| format!("Can not sync with PipeWire: {}", e))?;
+
+    while !ctx.cancellation_token.is_cancelled() {
+        while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+            on_keyboard(state.borrow().get_default_node(), &data);
+        }
+
+        if mainloop.loop_().iterate(LOOP_TIMEOUT) < 0 {
+            return Err("PipeWire loop error".to_string());
+        }
+
+        if let Some(message) = error.borrow_mut().take() {
+            return Err(format!("PipeWire connection error: {}", message));
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,124 @@
+use std::{
+    cell::{Cell, RefCell},
+    ops::Deref,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use libpulse_binding::{
+    callbacks::ListResult,
+    context::{introspect::Introspector, subscribe::Facility},
+    time::MicroSeconds,
+};
+use pulsectl::{controllers::types::DeviceInfo, Handler};
+
+use super::super::_base::{ProviderContext, ProviderResult};
+use super::DeviceKind;
+
+/// Mainloop is polled with timeout to check for cancellation and keyboard commands between events
+const MAINLOOP_TIMEOUT: MicroSeconds = MicroSeconds(100_000);
+
+/// Events within this interval after the first one are coalesced into a single query, e.g. while volume slider is dragged
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Special names resolved by the server, so default device change does not need a separate query
+const DEFAULT_SINK: &str = "@DEFAULT_SINK@";
+const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+
+type OnChange = Rc<RefCell<dyn FnMut(Option<&DeviceInfo>)>>;
+
+/// Volume of the device relative to its base volume and its mute flag
+pub fn get_volume(device: &DeviceInfo) -> Option<(f32, bool)> {
+    let device_volume = device.volume.get().first()?.0 as f32;
+    let base_volume = device.base_volume.0 as f32;
+    return Some((device_volume / base_volume, device.mute));
+}
+
+pub fn connect(name: &str) -> Result<Handler, String> {
+    return Handler::connect(name).map_err(|e| format!("Can not connect to PulseAudio: {}", e));
+}
+
+/// Queries default device through the subscribed context, result is handled during mainloop dispatch
+fn request_default_device(introspector: &Introspector, kind: DeviceKind, device: &Rc<RefCell<Option<DeviceInfo>>>, on_change: &OnChange) {
+    let device = device.clone();
+    let on_change = on_change.clone();
+    let update = move |info: Option<DeviceInfo>| {
+        *device.borrow_mut() = info;
+        (on_change.borrow_mut())(device.borrow().as_ref());
+    };
+
+    // Server returns error when there is no default device
+    match kind {
+        DeviceKind::Sink => {
+            introspector.get_sink_info_by_name(DEFAULT_SINK, move |result| match result {
+                ListResult::Item(info) => update(Some(DeviceInfo::from(info))),
+                ListResult::Error => update(None),
+                ListResult::End => (),
+            });
+        }
+        DeviceKind::Source => {
+            introspector.get_source_info_by_name(DEFAULT_SOURCE, move |result| match result {
+                ListResult::Item(info) => update(Some(DeviceInfo::from(info))),
+                ListResult::Error => update(None),
+                ListResult::End => (),
+            });
+        }
+    }
+}
+
+/// Watches default sink or source until cancelled. `on_change` is called with the default device after start and after changes,
+/// `on_keyboard` handles keyboard messages with the last known default device
+pub fn watch_default_device(
+    handler: Handler,
+    kind: DeviceKind,
+    mut ctx: ProviderContext,
+    on_change: impl FnMut(Option<&DeviceInfo>) + 'static,
+    mut on_keyboard: impl FnMut(&mut Introspector, Option<&DeviceInfo>, &[u8]),
+) -> ProviderResult {
+    let on_change: OnChange = Rc::new(RefCell::new(on_change));
+    let device = Rc::new(RefCell::new(None));
+    let mut pulse_ctx = handler.context.deref().borrow_mut();
+    let mut introspector = pulse_ctx.introspect();
+    request_default_device(&introspector, kind, &device, &on_change);
+
+    // Device is queried from the loop, so a burst of events results in one query
+    let changed_at = Rc::new(Cell::new(None));
+    let subscribe_changed_at = changed_at.clone();
+    pulse_ctx.set_subscribe_callback(Some(Box::new(move |_, _, _| {
+        if subscribe_changed_at.get().is_none() {
+            subscribe_changed_at.set(Some(Instant::now()));
+        }
+    })));
+
+    // Server events include default device change
+    let facility = match kind {
+        DeviceKind::Sink => Facility::Sink,
+        DeviceKind::Source => Facility::Source,
+    };
+    pulse_ctx.subscribe(facility.to_interest_mask() | Facility::Server.to_interest_mask(), |_| {});
+
+    while !ctx.cancellation_token.is_cancelled() {
+        while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+            on_keyboard(&mut introspector, device.borrow().as_ref(), &data);
+        }
+
+        if changed_at.get().is_some_and(|x| x.elapsed() >= DEBOUNCE_INTERVAL) {
+            changed_at.set(None);
+            request_default_device(&introspector, kind, &device, &on_change);
+        }
+
+        // Mainloop wakes up when pending events are due to be queried
+        let timeout = changed_at
+            .get()
+            .map(|x| MicroSeconds(DEBOUNCE_INTERVAL.saturating_sub(x.elapsed()).as_micros() as u64))
+            .unwrap_or(MAINLOOP_TIMEOUT);
+        let mut mainloop = handler.mainloop.deref().borrow_mut();
+        mainloop
+            .prepare(Some(timeout))
+            .map_err(|e| format!("PulseAudio mainloop error: {}", e))?;
+        mainloop.poll().map_err(|e| format!("PulseAudio mainloop error: {}", e))?;
+        mainloop.dispatch().map_err(|e| format!("PulseAudio mainloop error: {}", e))?;
+    }
+
+    return Ok(());
+}
@@ -1,3 +1,49 @@
-pub trait Provider {
-    fn start(&self);
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit};
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    /// Held by provider instance including its blocking code, supervisor does not start the next instance until it is released
+    pub static INSTANCE: Arc<OwnedSemaphorePermit>;
+}
+
+pub struct ProviderContext {
+    pub data_sender: mpsc::Sender<Vec<u8>>,
+    /// Messages sent by keyboard, every provider receives all of them
+    pub keyboard_receiver: broadcast::Receiver<Vec<u8>>,
+    /// Notified by time provider after system resume or wall clock jump, providers relying on elapsed time should subscribe and refresh
+    pub resume_sender: broadcast::Sender<()>,
+    /// Cancelled when keyboard is disconnected or app is stopping, `run` must return soon after that
+    pub cancellation_token: CancellationToken,
+}
+
+/// Error message should contain enough context to be logged as is
+pub type ProviderResult = Result<(), String>;
+
+#[async_trait::async_trait]
+pub trait Provider: Send + Sync {
+    /// Used in logs and health state, must be unique
+    fn name(&self) -> &str;
+
+    /// Runs until cancelled. Returned error or panic makes supervisor restart the provider
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult;
+}
+
+/// Runs blocking provider code on a dedicated thread, panics are propagated to the caller's task
+pub async fn run_blocking<F>(f: F) -> ProviderResult
+where
+    F: FnOnce() -> ProviderResult + Send + 'static,
+{
+    // Blocking code can not be aborted, so it keeps the instance until it returns
+    let instance = INSTANCE.try_with(|x| x.clone()).ok();
+    let task = tokio::task::spawn_blocking(move || {
+        let _instance = instance;
+        return f();
+    });
+    return match task.await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(e.to_string()),
+    };
 }
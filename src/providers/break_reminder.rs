@@ -0,0 +1,188 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{config::BreakReminderProviderConfig, data_type::DataType};
+
+use super::_base::{Provider, ProviderContext, ProviderResult};
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+use self::linux::IdleTime;
+
+#[cfg(target_os = "windows")]
+mod windows;
+
+#[cfg(target_os = "windows")]
+use self::windows::IdleTime;
+
+#[cfg(target_os = "macos")]
+mod macos;
+
+#[cfg(target_os = "macos")]
+use self::macos::IdleTime;
+
+fn get_minutes(duration: Duration) -> [u8; 2] {
+    return ((duration.as_secs() / 60).min(u16::MAX as u64) as u16).to_le_bytes();
+}
+
+fn send_data(data: Vec<u8>, data_sender: &mpsc::Sender<Vec<u8>>) {
+    data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+}
+
+/// Continuous activity, ended by idle period longer than `idleThreshold` or system sleep
+#[derive(Default)]
+struct Session {
+    started_at: Option<Instant>,
+    reminded_at: Option<Instant>,
+}
+
+impl Session {
+    fn get_length(&self) -> Duration {
+        return self.started_at.map(|x| x.elapsed()).unwrap_or_default();
+    }
+
+    fn update(&mut self, idle: Duration, idle_threshold: Duration) {
+        if idle >= idle_threshold {
+            if self.started_at.is_some() {
+                tracing::info!("Break detected after {} minutes of activity", self.get_length().as_secs() / 60);
+            }
+
+            *self = Session::default();
+        } else if self.started_at.is_none() {
+            self.started_at = Instant::now().checked_sub(idle).or(Some(Instant::now()));
+        }
+    }
+}
+
+pub struct BreakReminderProvider {
+    config: BreakReminderProviderConfig,
+    /// Kept across runs, so reconnecting keyboard or restarting provider does not count activity from zero
+    session: Mutex<Session>,
+}
+
+impl BreakReminderProvider {
+    pub fn new(config: BreakReminderProviderConfig) -> Box<dyn Provider> {
+        let provider = BreakReminderProvider {
+            config,
+            session: Mutex::new(Session::default()),
+        };
+        return Box::new(provider);
+    }
+
+    fn update_session<T>(&self, f: impl FnOnce(&mut Session) -> T) -> T {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        return f(&mut session);
+    }
+
+    /// Returns `[type, session length in minutes as u16 LE, 1 if break is due]`
+    fn get_session_length(&self) -> Vec<u8> {
+        let length = self.update_session(|x| x.get_length());
+        let mut data = vec![DataType::SessionLength as u8];
+        data.extend(get_minutes(length));
+        data.push((length >= Duration::from_millis(self.config.break_interval)) as u8);
+        return data;
+    }
+
+    /// Updates session with current idle time. Reminder is sent once `breakInterval` is reached
+    /// and repeated every `reminderInterval` until the break
+    fn update(&self, idle: Duration, data_sender: &mpsc::Sender<Vec<u8>>) {
+        let idle_threshold = Duration::from_millis(self.config.idle_threshold);
+        self.update_session(|x| {
+            x.update(idle, idle_threshold);
+            self.remind(x, data_sender);
+        });
+    }
+
+    fn remind(&self, session: &mut Session, data_sender: &mpsc::Sender<Vec<u8>>) {
+        let length = session.get_length();
+        let reminder_interval = Duration::from_millis(self.config.reminder_interval);
+        let is_due = length >= Duration::from_millis(self.config.break_interval)
+            && session
+                .reminded_at
+                .is_none_or(|x| !reminder_interval.is_zero() && x.elapsed() >= reminder_interval);
+        if is_due {
+            tracing::info!("Time for a break after {} minutes of activity", length.as_secs() / 60);
+            session.reminded_at = Some(Instant::now());
+            let mut data = vec![DataType::BreakReminder as u8];
+            data.extend(get_minutes(length));
+            send_data(data, data_sender);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for BreakReminderProvider {
+    fn name(&self) -> &str {
+        return "Break reminder";
+    }
+
+    async fn run(&self, mut ctx: ProviderContext) -> ProviderResult {
+        let mut resume_receiver = ctx.resume_sender.subscribe();
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+
+        // Reader thread keeps platform connections open for the whole run and stops when request sender is dropped
+        let (request_sender, mut request_receiver) = mpsc::channel::<()>(1);
+        let (idle_sender, mut idle_receiver) = mpsc::channel::<Result<Duration, String>>(1);
+        tokio::task::spawn_blocking(move || {
+            let mut idle_time = IdleTime::new();
+            while request_receiver.blocking_recv().is_some() {
+                if idle_sender.blocking_send(idle_time.get()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            request_sender.send(()).await.map_err(|_| "Idle time reader stopped")?;
+            let idle = idle_receiver.recv().await.ok_or("Idle time reader stopped")??;
+            self.update(idle, &ctx.data_sender);
+
+            tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(poll_interval) => (),
+                _ = resume_receiver.recv() => self.update_session(|x| *x = Session::default()),
+                Ok(data) = ctx.keyboard_receiver.recv() => {
+                    if data.first() == Some(&(DataType::SessionLength as u8)) {
+                        send_data(self.get_session_length(), &ctx.data_sender);
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_is_reset_only_by_idle() {
+        let provider = BreakReminderProvider {
+            config: BreakReminderProviderConfig {
+                break_interval: 60_000,
+                reminder_interval: 0,
+                idle_threshold: 300_000,
+                ..Default::default()
+            },
+            session: Mutex::new(Session::default()),
+        };
+        let (data_sender, mut data_receiver) = mpsc::channel(10);
+
+        provider.update(Duration::from_secs(120), &data_sender);
+        assert_eq!(data_receiver.try_recv(), Ok(vec![DataType::BreakReminder as u8, 2, 0]));
+        provider.update(Duration::ZERO, &data_sender);
+        assert!(data_receiver.try_recv().is_err());
+        assert_eq!(provider.get_session_length(), vec![DataType::SessionLength as u8, 2, 0, 1]);
+
+        provider.update(Duration::from_secs(400), &data_sender);
+        assert_eq!(provider.get_session_length(), vec![DataType::SessionLength as u8, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    ptr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dbus::blocking::{stdintf::org_freedesktop_dbus::Properties, Connection};
+use x11::{
+    xlib::{Display, XCloseDisplay, XDefaultRootWindow, XFree, XOpenDisplay},
+    xss::{XScreenSaverAllocInfo, XScreenSaverQueryExtension, XScreenSaverQueryInfo},
+};
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+/// Session of the caller or user's graphical session if the app is not a part of any session
+const LOGIND_SESSION: &str = "/org/freedesktop/login1/session/auto";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const DBUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn get_x11_idle_time(display: *mut Display) -> Result<Duration, String> {
+    let (mut event_base, mut error_base) = (0, 0);
+    if unsafe { XScreenSaverQueryExtension(display, &mut event_base, &mut error_base) } == 0 {
+        return Err("X11 screensaver extension is not available".to_string());
+    }
+
+    let info = unsafe { XScreenSaverAllocInfo() };
+    if info.is_null() {
+        return Err("Can not allocate X11 screensaver info".to_string());
+    }
+
+    let status = unsafe { XScreenSaverQueryInfo(display, XDefaultRootWindow(display), info) };
+    let idle = unsafe { info.read().idle };
+    unsafe { XFree(info.cast()) };
+    if status == 0 {
+        return Err("Can not query X11 screensaver info".to_string());
+    }
+
+    return Ok(Duration::from_millis(idle as u64));
+}
+
+/// Idle hint is set by desktop environment, usually after its own idle delay
+fn get_logind_idle_time(connection: &Connection) -> Result<Duration, String> {
+    let session = connection.with_proxy(LOGIND_SERVICE, LOGIND_SESSION, DBUS_TIMEOUT);
+    let idle: bool = session
+        .get(LOGIND_SESSION_INTERFACE, "IdleHint")
+        .map_err(|e| format!("Can not get logind idle hint: {}", e))?;
+    if !idle {
+        return Ok(Duration::ZERO);
+    }
+
+    let idle_since: u64 = session
+        .get(LOGIND_SESSION_INTERFACE, "IdleSinceHint")
+        .map_err(|e| format!("Can not get logind idle time: {}", e))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    return Ok(now.saturating_sub(Duration::from_micros(idle_since)));
+}
+
+/// X11 screensaver extension is used on X11, logind idle hint on Wayland or if X11 is not available.
+/// Display and D-Bus connection are opened once and reused for every poll
+pub struct IdleTime {
+    /// Null on Wayland or if X11 display can not be opened
+    display: *mut Display,
+    /// Opened on first use and opened again after an error
+    connection: Option<Connection>,
+}
+
+impl IdleTime {
+    pub fn new() -> Self {
+        let display = match std::env::var_os("WAYLAND_DISPLAY") {
+            Some(_) => ptr::null_mut(),
+            None => unsafe { XOpenDisplay(ptr::null()) },
+        };
+        if display.is_null() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            tracing::warn!("Can not open X11 display, using logind idle hint");
+        }
+
+        return Self { display, connection: None };
+    }
+
+    fn get_logind_idle_time(&mut self) -> Result<Duration, String> {
+        let connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => Connection::new_system().map_err(|e| format!("Can not connect to system D-Bus: {}", e))?,
+        };
+        let result = get_logind_idle_time(&connection);
+        if result.is_ok() {
+            self.connection = Some(connection);
+        }
+
+        return result;
+    }
+
+    pub fn get(&mut self) -> Result<Duration, String> {
+        if self.display.is_null() {
+            return self.get_logind_idle_time();
+        }
+
+        return get_x11_idle_time(self.display)
+            .or_else(|x11_error| self.get_logind_idle_time().map_err(|e| format!("{}; {}", x11_error, e)));
+    }
+}
+
+impl Drop for IdleTime {
+    fn drop(&mut self) {
+        if !self.display.is_null() {
+            unsafe { XCloseDisplay(self.display) };
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+// kCGEventSourceStateCombinedSessionState and kCGAnyInputEventType
+const COMBINED_SESSION_STATE: i32 = 0;
+const ANY_INPUT_EVENT_TYPE: u32 = u32::MAX;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+}
+
+pub struct IdleTime;
+
+impl IdleTime {
+    pub fn new() -> Self {
+        return Self;
+    }
+
+    pub fn get(&mut self) -> Result<Duration, String> {
+        let seconds = unsafe { CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT_TYPE) };
+        return Duration::try_from_secs_f64(seconds).map_err(|e| format!("Invalid idle time {}: {}", seconds, e));
+    }
+}
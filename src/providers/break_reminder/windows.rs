@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use windows::Win32::{
+    System::SystemInformation::GetTickCount,
+    UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO},
+};
+
+pub struct IdleTime;
+
+impl IdleTime {
+    pub fn new() -> Self {
+        return Self;
+    }
+
+    pub fn get(&mut self) -> Result<Duration, String> {
+        let mut info = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        unsafe { GetLastInputInfo(&mut info) }
+            .ok()
+            .map_err(|e| format!("Can not get last input time: {}", e))?;
+
+        // both values are milliseconds since system start and wrap around after 49 days
+        let now = unsafe { GetTickCount() };
+        return Ok(Duration::from_millis(now.wrapping_sub(info.dwTime) as u64));
+    }
+}
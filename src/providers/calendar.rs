@@ -0,0 +1,506 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use ical::{parser::ical::component::IcalEvent, property::Property, IcalParser};
+use tokio::sync::mpsc;
+
+use crate::{config::CalendarProviderConfig, data_type::DataType};
+
+use super::_base::{Provider, ProviderContext, ProviderResult};
+
+mod recurrence;
+
+use recurrence::Rule;
+
+/// How far ahead the next event is searched
+const LOOKAHEAD: chrono::Duration = chrono::Duration::days(14);
+/// Sent as minutes until start when there are no events in `LOOKAHEAD`
+const NO_EVENT: u16 = u16::MAX;
+
+#[derive(Clone, Copy)]
+enum EventZone {
+    Utc,
+    /// Floating time or unknown `TZID`
+    Local,
+    Tz(Tz),
+}
+
+impl EventZone {
+    fn to_utc(self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        return match self {
+            EventZone::Utc => Some(time.and_utc()),
+            EventZone::Local => Local.from_local_datetime(&time).earliest().map(|x| x.to_utc()),
+            EventZone::Tz(tz) => tz.from_local_datetime(&time).earliest().map(|x| x.to_utc()),
+        };
+    }
+
+    fn from_utc(self, time: DateTime<Utc>) -> NaiveDateTime {
+        return match self {
+            EventZone::Utc => time.naive_utc(),
+            EventZone::Local => time.with_timezone(&Local).naive_local(),
+            EventZone::Tz(tz) => time.with_timezone(&tz).naive_local(),
+        };
+    }
+}
+
+struct Event {
+    uid: String,
+    title: String,
+    start: NaiveDateTime,
+    zone: EventZone,
+    rule: Option<Rule>,
+    exceptions: HashSet<DateTime<Utc>>,
+    /// Set for modified occurrences of recurring events, replaces the original occurrence
+    recurrence_id: Option<DateTime<Utc>>,
+    /// Cancelled modified occurrence, only hides the original occurrence
+    cancelled: bool,
+}
+
+#[derive(Clone, PartialEq)]
+struct Occurrence {
+    uid: String,
+    title: String,
+    start: DateTime<Utc>,
+}
+
+fn get_property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    return event.properties.iter().find(|x| x.name == name);
+}
+
+fn get_param<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    return property
+        .params
+        .as_ref()?
+        .iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, values)| values.first())
+        .map(|x| x.as_str());
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(x) = chars.next() {
+        match x {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => result.push(' '),
+                Some(x) => result.push(x),
+                None => (),
+            },
+            x => result.push(x),
+        }
+    }
+
+    return result;
+}
+
+fn get_zone(property: &Property) -> EventZone {
+    return match get_param(property, "TZID") {
+        Some(tzid) => tzid.parse::<Tz>().map(EventZone::Tz).unwrap_or_else(|_| {
+            tracing::debug!("Unknown time zone {}, using local time", tzid);
+            EventZone::Local
+        }),
+        None => EventZone::Local,
+    };
+}
+
+/// Parses `20261018T090000Z` or `20261018T090000`, all-day values like `20261018` are not supported
+fn parse_date_time(value: &str, zone: EventZone) -> Option<(NaiveDateTime, EventZone)> {
+    let (value, zone) = match value.strip_suffix('Z') {
+        Some(x) => (x, EventZone::Utc),
+        None => (value, zone),
+    };
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    return Some((time, zone));
+}
+
+/// Returns UTC times of all comma-separated values of all properties with `name`
+fn get_times(event: &IcalEvent, name: &str) -> HashSet<DateTime<Utc>> {
+    return event
+        .properties
+        .iter()
+        .filter(|x| x.name == name)
+        .flat_map(|property| {
+            let zone = get_zone(property);
+            property
+                .value
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(move |x| parse_date_time(x, zone).and_then(|(time, zone)| zone.to_utc(time)))
+        })
+        .collect();
+}
+
+/// Returns `None` for cancelled, all-day and invalid events, cancelled occurrences of recurring events are kept
+fn parse_event(event: &IcalEvent) -> Option<Event> {
+    let recurrence_id = get_times(event, "RECURRENCE-ID").into_iter().next();
+    let cancelled = get_property(event, "STATUS").and_then(|x| x.value.as_deref()) == Some("CANCELLED");
+    if cancelled && recurrence_id.is_none() {
+        return None;
+    }
+
+    let start = get_property(event, "DTSTART")?;
+    let (start_time, zone) = parse_date_time(start.value.as_deref()?, get_zone(start))?;
+    let title = get_property(event, "SUMMARY")
+        .and_then(|x| x.value.as_deref())
+        .map(unescape_text)
+        .unwrap_or_default();
+    let uid = get_property(event, "UID").and_then(|x| x.value.clone()).unwrap_or_default();
+
+    let rule = match get_property(event, "RRULE").and_then(|x| x.value.as_deref()) {
+        Some(value) => {
+            let parse_until = |x: &str| match parse_date_time(x, zone) {
+                Some((time, EventZone::Utc)) => Some(zone.from_utc(time.and_utc())),
+                Some((time, _)) => Some(time),
+                None => chrono::NaiveDate::parse_from_str(x, "%Y%m%d").ok()?.and_hms_opt(23, 59, 59),
+            };
+            match Rule::parse(value, parse_until) {
+                Ok(rule) => Some(rule),
+                Err(e) => {
+                    tracing::warn!("Calendar event {:?} is skipped, can not parse rule {}: {}", title, value, e);
+                    return None;
+                }
+            }
+        }
+        None => None,
+    };
+
+    return Some(Event {
+        uid,
+        title,
+        start: start_time,
+        zone,
+        rule,
+        exceptions: get_times(event, "EXDATE"),
+        recurrence_id,
+        cancelled,
+    });
+}
+
+fn is_calendar_file(path: &Path) -> bool {
+    return path.extension().is_some_and(|x| x.eq_ignore_ascii_case("ics"));
+}
+
+/// Collects `.ics` files, directories are searched recursively like vdirsyncer or khal storage
+fn find_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(path) else {
+        tracing::warn!("Calendar path {} not found", path.display());
+        return;
+    };
+
+    for path in entries.filter_map(|x| x.ok().map(|x| x.path())) {
+        if path.is_dir() {
+            find_files(&path, files);
+        } else if is_calendar_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn read_events(path: &Path) -> Result<Vec<Event>, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut events = vec![];
+    for calendar in IcalParser::new(BufReader::new(file)) {
+        let calendar = calendar.map_err(|e| e.to_string())?;
+        events.extend(calendar.events.iter().filter_map(parse_event));
+    }
+
+    return Ok(events);
+}
+
+/// Parsed events from all configured files, files are parsed again only when changed
+#[derive(Default)]
+struct Calendar {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    events: Vec<Event>,
+}
+
+impl Calendar {
+    fn reload(mut self, paths: &[String]) -> Self {
+        let mut files = vec![];
+        paths.iter().for_each(|x| find_files(Path::new(x), &mut files));
+        files.sort();
+        let files = files
+            .into_iter()
+            .map(|x| {
+                let modified = std::fs::metadata(&x).and_then(|x| x.modified()).ok();
+                (x, modified)
+            })
+            .collect::<Vec<_>>();
+        if files == self.files {
+            return self;
+        }
+
+        self.events = files
+            .iter()
+            .flat_map(|(path, _)| {
+                read_events(path).unwrap_or_else(|e| {
+                    tracing::warn!("Can not read calendar {}: {}", path.display(), e);
+                    vec![]
+                })
+            })
+            .collect();
+        tracing::info!("Calendar loaded {} events from {} files", self.events.len(), files.len());
+        self.files = files;
+        return self;
+    }
+
+    /// Occurrences starting after `from`, sorted by start
+    fn get_occurrences(&self, from: DateTime<Utc>) -> Vec<Occurrence> {
+        let to = from + LOOKAHEAD;
+        let overridden = self
+            .events
+            .iter()
+            .filter_map(|x| x.recurrence_id.map(|id| (x.uid.as_str(), id)))
+            .fold(HashMap::<&str, HashSet<DateTime<Utc>>>::new(), |mut map, (uid, id)| {
+                map.entry(uid).or_default().insert(id);
+                map
+            });
+
+        let mut occurrences = vec![];
+        for event in self.events.iter().filter(|x| !x.cancelled) {
+            let starts = match (&event.rule, event.recurrence_id) {
+                (Some(rule), None) => rule.occurrences(event.start, event.zone.from_utc(from), event.zone.from_utc(to)),
+                _ => vec![event.start],
+            };
+            occurrences.extend(
+                starts
+                    .into_iter()
+                    .filter_map(|x| event.zone.to_utc(x))
+                    .filter(|x| *x > from && *x <= to)
+                    .filter(|x| !event.exceptions.contains(x))
+                    .filter(|x| event.recurrence_id.is_some() || !overridden.get(event.uid.as_str()).is_some_and(|ids| ids.contains(x)))
+                    .map(|start| Occurrence {
+                        uid: event.uid.clone(),
+                        title: event.title.clone(),
+                        start,
+                    }),
+            );
+        }
+
+        occurrences.sort_by_key(|x| x.start);
+        return occurrences;
+    }
+}
+
+/// Rounded up, so event starting in 4.5 minutes is shown as 5 minutes away
+fn get_minutes_until(start: DateTime<Utc>, now: DateTime<Utc>) -> u16 {
+    let seconds = (start - now).num_seconds().max(0);
+    return ((seconds + 59) / 60).min((NO_EVENT - 1) as i64) as u16;
+}
+
+fn send_data(data: Vec<u8>, data_sender: &mpsc::Sender<Vec<u8>>) {
+    data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+}
+
+pub struct CalendarProvider {
+    config: CalendarProviderConfig,
+    /// Occurrences already alerted, kept across runs so reconnecting keyboard does not get the same alerts again
+    alerted: Mutex<HashSet<(String, DateTime<Utc>)>>,
+}
+
+impl CalendarProvider {
+    pub fn new(config: CalendarProviderConfig) -> Box<dyn Provider> {
+        let provider = CalendarProvider {
+            config,
+            alerted: Mutex::new(HashSet::new()),
+        };
+        return Box::new(provider);
+    }
+
+    fn get_title(&self, title: &str) -> Vec<u8> {
+        let mut title = title.as_bytes().to_vec();
+        title.truncate(self.config.max_length);
+        title.insert(0, title.len() as u8);
+        return title;
+    }
+
+    /// Returns `[type, minutes until start as u16 LE, title length, ...title]`
+    fn get_next_event(&self, next: Option<&(u16, String)>) -> Vec<u8> {
+        let (minutes, title) = next.map(|(minutes, title)| (*minutes, title.as_str())).unwrap_or((NO_EVENT, ""));
+        let mut data = vec![DataType::CalendarEvent as u8];
+        data.extend_from_slice(&minutes.to_le_bytes());
+        data.extend(self.get_title(title));
+        return data;
+    }
+
+    /// Returns `[type, minutes until start, title length, ...title]`
+    fn get_alert(&self, minutes: u16, title: &str) -> Vec<u8> {
+        let mut data = vec![DataType::CalendarAlert as u8, minutes.min(u8::MAX as u16) as u8];
+        data.extend(self.get_title(title));
+        return data;
+    }
+
+    /// Sends alerts for occurrences starting within `alertLeadTime`, each occurrence is alerted once
+    fn send_alerts(&self, occurrences: &[Occurrence], now: DateTime<Utc>, data_sender: &mpsc::Sender<Vec<u8>>) {
+        let alert_lead_time = chrono::Duration::minutes(self.config.alert_lead_time as i64);
+        let mut alerted = self.alerted.lock().unwrap();
+        alerted.retain(|(_, start)| *start > now);
+        for occurrence in occurrences.iter().take_while(|x| x.start - now <= alert_lead_time) {
+            if alerted.insert((occurrence.uid.clone(), occurrence.start)) {
+                tracing::info!(
+                    "Calendar event {:?} starts at {}",
+                    occurrence.title,
+                    occurrence.start.with_timezone(&Local)
+                );
+                let minutes = get_minutes_until(occurrence.start, now);
+                send_data(self.get_alert(minutes, &occurrence.title), data_sender);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for CalendarProvider {
+    fn name(&self) -> &str {
+        return "Calendar";
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let mut resume_receiver = ctx.resume_sender.subscribe();
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        let mut calendar = Calendar::default();
+        let mut loaded_at: Option<Instant> = None;
+        let mut synced: Option<Option<(u16, String)>> = None;
+        loop {
+            if loaded_at.is_none_or(|x| x.elapsed() >= poll_interval) {
+                let paths = self.config.paths.clone();
+                calendar = tokio::task::spawn_blocking(move || calendar.reload(&paths))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                loaded_at = Some(Instant::now());
+            }
+
+            let now = Utc::now();
+            let occurrences = calendar.get_occurrences(now);
+            let next = occurrences.first().map(|x| (get_minutes_until(x.start, now), x.title.clone()));
+            if synced.as_ref() != Some(&next) {
+                send_data(self.get_next_event(next.as_ref()), &ctx.data_sender);
+                synced = Some(next);
+            }
+
+            self.send_alerts(&occurrences, now, &ctx.data_sender);
+
+            let now = Local::now();
+            let to_next_minute =
+                Duration::from_secs(60 - now.second().min(59) as u64) - Duration::from_nanos(now.nanosecond().min(999_999_999) as u64);
+            tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(to_next_minute.min(poll_interval)) => (),
+                _ = resume_receiver.recv() => loaded_at = None,
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str) -> Calendar {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/calendar").join(name);
+        return Calendar::default().reload(&[path.to_string_lossy().to_string()]);
+    }
+
+    /// Titles and UTC starts of occurrences in `LOOKAHEAD` after 2026-10-18
+    fn get_starts(calendar: &Calendar) -> Vec<(String, String)> {
+        let from = Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap();
+        return calendar
+            .get_occurrences(from)
+            .into_iter()
+            .map(|x| (x.title, x.start.format("%Y-%m-%d %H:%M").to_string()))
+            .collect();
+    }
+
+    fn expected(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        return items.iter().map(|(title, start)| (title.to_string(), start.to_string())).collect();
+    }
+
+    #[test]
+    fn daily_rule_stops_at_count() {
+        let starts = get_starts(&load("daily.ics"));
+        let days = ["19", "20", "21", "22", "23"].map(|x| format!("2026-10-{} 09:00", x));
+        assert_eq!(starts, expected(&days.iter().map(|x| ("Daily", x.as_str())).collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn weekly_rule_uses_time_zone_and_until() {
+        // Europe/Berlin switches from UTC+2 to UTC+1 on 2026-10-25
+        let starts = get_starts(&load("weekly.ics"));
+        assert_eq!(
+            starts,
+            expected(&[
+                ("Weekly", "2026-10-19 08:00"),
+                ("Weekly", "2026-10-21 08:00"),
+                ("Weekly", "2026-10-23 08:00"),
+                ("Weekly", "2026-10-26 09:00"),
+                ("Weekly", "2026-10-28 09:00"),
+            ])
+        );
+    }
+
+    #[test]
+    fn monthly_rule_uses_weekday_position() {
+        assert_eq!(get_starts(&load("monthly.ics")), expected(&[("Monthly", "2026-10-30 15:00")]));
+    }
+
+    #[test]
+    fn excluded_dates_are_skipped() {
+        let starts = get_starts(&load("exdate.ics"));
+        assert_eq!(starts, expected(&[("Exdate", "2026-10-19 13:00"), ("Exdate", "2026-10-22 13:00")]));
+    }
+
+    #[test]
+    fn modified_occurrences_replace_original() {
+        let starts = get_starts(&load("override.ics"));
+        assert_eq!(
+            starts,
+            expected(&[("Standup", "2026-10-19 09:00"), ("Standup moved", "2026-10-20 11:00")])
+        );
+    }
+
+    #[test]
+    fn unsupported_events_are_skipped() {
+        let calendar = load("skipped.ics");
+        assert_eq!(calendar.events.len(), 1);
+        assert_eq!(get_starts(&calendar), expected(&[("Single", "2026-10-20 09:00")]));
+    }
+
+    #[test]
+    fn alerts_are_sent_once_across_runs() {
+        let provider = CalendarProvider {
+            config: CalendarProviderConfig::default(),
+            alerted: Mutex::new(HashSet::new()),
+        };
+        let (data_sender, mut data_receiver) = mpsc::channel(10);
+        let now = Utc.with_ymd_and_hms(2026, 10, 18, 9, 0, 0).unwrap();
+        let occurrences = [Occurrence {
+            uid: "single@test".to_string(),
+            title: "Single".to_string(),
+            start: now + chrono::Duration::minutes(3),
+        }];
+
+        provider.send_alerts(&occurrences, now, &data_sender);
+        provider.send_alerts(&occurrences, now + chrono::Duration::minutes(1), &data_sender);
+        assert_eq!(
+            data_receiver.try_recv().unwrap(),
+            [&[DataType::CalendarAlert as u8, 3, 6][..], b"Single"].concat()
+        );
+        assert!(data_receiver.try_recv().is_err());
+    }
+}
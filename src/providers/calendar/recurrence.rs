@@ -0,0 +1,357 @@
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, Weekday};
+
+/// Protects from rules that never produce occurrences in the requested range
+const MAX_PERIODS: u32 = 100_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// Supported subset of RFC 5545 `RRULE`: `FREQ`, `INTERVAL`, `COUNT`, `UNTIL`, `BYDAY` and `BYMONTHDAY`
+pub struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    /// Inclusive, in the same zone as event start
+    until: Option<NaiveDateTime>,
+    /// Weekdays with optional position in month, like `2TU` or `-1FR`
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+}
+
+fn parse_weekday(value: &str) -> Result<(Option<i32>, Weekday), String> {
+    let split = value.len().saturating_sub(2);
+    let (position, weekday) = value.split_at_checked(split).ok_or(format!("invalid weekday {}", value))?;
+    let weekday = match weekday {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(format!("invalid weekday {}", value)),
+    };
+    let position = match position {
+        "" => None,
+        x => Some(x.parse::<i32>().map_err(|_| format!("invalid weekday {}", value))?),
+    };
+    return Ok((position, weekday));
+}
+
+fn last_day_of_month(first: NaiveDate) -> Option<NaiveDate> {
+    return first.checked_add_months(Months::new(1))?.pred_opt();
+}
+
+impl Rule {
+    /// `parse_until` converts `UNTIL` value to event zone
+    pub fn parse(value: &str, parse_until: impl Fn(&str) -> Option<NaiveDateTime>) -> Result<Self, String> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+        };
+        let mut frequency = None;
+        for part in value.split(';').filter(|x| !x.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or(format!("invalid rule part {}", part))?;
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        x => return Err(format!("unsupported frequency {}", x)),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse::<u32>().ok().filter(|x| *x > 0).ok_or("invalid interval")?,
+                "COUNT" => rule.count = Some(value.parse::<u32>().map_err(|_| "invalid count")?),
+                "UNTIL" => rule.until = Some(parse_until(value).ok_or(format!("invalid until {}", value))?),
+                "BYDAY" => rule.by_day = value.split(',').map(parse_weekday).collect::<Result<_, _>>()?,
+                "BYMONTHDAY" => {
+                    rule.by_month_day = value
+                        .split(',')
+                        .map(|x| x.parse::<i32>().map_err(|_| format!("invalid month day {}", x)))
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" => (),
+                x => return Err(format!("unsupported rule part {}", x)),
+            }
+        }
+
+        rule.frequency = frequency.ok_or("frequency is missing")?;
+        let has_position = rule.by_day.iter().any(|(position, _)| position.is_some());
+        if has_position && rule.frequency != Frequency::Monthly {
+            return Err("weekday position is supported only for monthly rules".to_string());
+        }
+
+        if !rule.by_month_day.is_empty() && rule.frequency != Frequency::Monthly {
+            return Err("month day is supported only for monthly rules".to_string());
+        }
+
+        return Ok(rule);
+    }
+
+    /// Dates matching the rule in the period `offset` intervals after the one containing `start`
+    fn period_dates(&self, start: NaiveDate, offset: u32) -> Vec<NaiveDate> {
+        return match self.frequency {
+            Frequency::Daily => start
+                .checked_add_days(Days::new(offset as u64))
+                .filter(|x| self.by_day.is_empty() || self.by_day.iter().any(|(_, weekday)| *weekday == x.weekday()))
+                .into_iter()
+                .collect(),
+            Frequency::Weekly => {
+                let Some(monday) = start
+                    .checked_sub_days(Days::new(start.weekday().num_days_from_monday() as u64))
+                    .and_then(|x| x.checked_add_days(Days::new(offset as u64 * 7)))
+                else {
+                    return vec![];
+                };
+                let weekdays = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|(_, x)| *x).collect()
+                };
+                weekdays
+                    .into_iter()
+                    .filter_map(|x| monday.checked_add_days(Days::new(x.num_days_from_monday() as u64)))
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let Some((first, last)) = start
+                    .with_day(1)
+                    .and_then(|x| x.checked_add_months(Months::new(offset)))
+                    .and_then(|x| Some((x, last_day_of_month(x)?)))
+                else {
+                    return vec![];
+                };
+                if !self.by_month_day.is_empty() {
+                    self.by_month_day
+                        .iter()
+                        .filter_map(|x| match *x {
+                            x if x > 0 => first.with_day(x as u32),
+                            x if x < 0 => last
+                                .checked_sub_days(Days::new(x.unsigned_abs() as u64 - 1))
+                                .filter(|x| x.month() == first.month()),
+                            _ => None,
+                        })
+                        .collect()
+                } else if !self.by_day.is_empty() {
+                    self.by_day
+                        .iter()
+                        .flat_map(|(position, weekday)| {
+                            let days = first
+                                .iter_days()
+                                .take_while(|x| *x <= last)
+                                .filter(|x| x.weekday() == *weekday)
+                                .collect::<Vec<_>>();
+                            match *position {
+                                Some(x) if x > 0 => days.get(x as usize - 1).copied().into_iter().collect(),
+                                Some(x) if x < 0 => days
+                                    .len()
+                                    .checked_sub(x.unsigned_abs() as usize)
+                                    .map(|i| days[i])
+                                    .into_iter()
+                                    .collect(),
+                                Some(_) => vec![],
+                                None => days,
+                            }
+                        })
+                        .collect()
+                } else {
+                    first.with_day(start.day()).into_iter().collect()
+                }
+            }
+            Frequency::Yearly => i32::try_from(offset)
+                .ok()
+                .and_then(|x| start.with_year(start.year().checked_add(x)?))
+                .into_iter()
+                .collect(),
+        };
+    }
+
+    /// Index of the period containing `from`, earlier periods can't have occurrences in range
+    fn first_period(&self, start: NaiveDate, from: NaiveDate) -> u32 {
+        let units = match self.frequency {
+            Frequency::Daily => from.signed_duration_since(start).num_days(),
+            Frequency::Weekly => {
+                let days = from.signed_duration_since(start).num_days() + start.weekday().num_days_from_monday() as i64;
+                days.div_euclid(7)
+            }
+            Frequency::Monthly => (from.year() as i64 - start.year() as i64) * 12 + from.month0() as i64 - start.month0() as i64,
+            Frequency::Yearly => from.year() as i64 - start.year() as i64,
+        };
+        return u32::try_from(units.max(0) / self.interval as i64).unwrap_or(u32::MAX);
+    }
+
+    /// Occurrences from `from` to `to` (inclusive) of event starting at `start`, which is always the first occurrence
+    pub fn occurrences(&self, start: NaiveDateTime, from: NaiveDateTime, to: NaiveDateTime) -> Vec<NaiveDateTime> {
+        let mut result = vec![];
+        let mut count = 0;
+        let mut add = |time: NaiveDateTime| -> bool {
+            count += 1;
+            if time > to || self.until.is_some_and(|x| time > x) || self.count.is_some_and(|x| count > x) {
+                return false;
+            }
+
+            if time >= from {
+                result.push(time);
+            }
+
+            return true;
+        };
+
+        if !add(start) {
+            return result;
+        }
+
+        // COUNT needs every occurrence from the start, otherwise periods before `from` are skipped
+        let first = match self.count {
+            Some(_) => 0,
+            None => self.first_period(start.date(), from.date()),
+        };
+        for period in first..first.saturating_add(MAX_PERIODS) {
+            let mut dates = self.period_dates(start.date(), period.saturating_mul(self.interval));
+            dates.sort();
+            dates.dedup();
+            for time in dates.into_iter().map(|x| x.and_time(start.time())).filter(|x| *x > start) {
+                if !add(time) {
+                    return result;
+                }
+            }
+        }
+
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_time(value: &str) -> NaiveDateTime {
+        return NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").unwrap();
+    }
+
+    fn get_occurrences(rule: &str, start: &str, from: &str, to: &str) -> Vec<String> {
+        let rule = Rule::parse(rule, |x| NaiveDateTime::parse_from_str(x, "%Y%m%dT%H%M%S").ok()).unwrap();
+        return rule
+            .occurrences(parse_time(start), parse_time(from), parse_time(to))
+            .into_iter()
+            .map(|x| x.format("%Y%m%dT%H%M%S").to_string())
+            .collect();
+    }
+
+    #[test]
+    fn daily_rule_uses_interval() {
+        let occurrences = get_occurrences(
+            "FREQ=DAILY;INTERVAL=2;COUNT=4",
+            "20261019T090000",
+            "20261001T000000",
+            "20261231T000000",
+        );
+        assert_eq!(
+            occurrences,
+            ["20261019T090000", "20261021T090000", "20261023T090000", "20261025T090000"]
+        );
+    }
+
+    #[test]
+    fn count_includes_occurrences_before_range() {
+        let occurrences = get_occurrences("FREQ=DAILY;COUNT=5", "20261019T090000", "20261022T000000", "20261231T000000");
+        assert_eq!(occurrences, ["20261022T090000", "20261023T090000"]);
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let occurrences = get_occurrences(
+            "FREQ=WEEKLY;UNTIL=20261102T090000",
+            "20261019T090000",
+            "20261001T000000",
+            "20261231T000000",
+        );
+        assert_eq!(occurrences, ["20261019T090000", "20261026T090000", "20261102T090000"]);
+    }
+
+    #[test]
+    fn weekly_rule_uses_weekdays() {
+        let occurrences = get_occurrences(
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;WKST=MO",
+            "20261020T090000",
+            "20261001T000000",
+            "20261106T000000",
+        );
+        assert_eq!(
+            occurrences,
+            ["20261020T090000", "20261022T090000", "20261103T090000", "20261105T090000"]
+        );
+    }
+
+    #[test]
+    fn monthly_rule_skips_short_months() {
+        let occurrences = get_occurrences("FREQ=MONTHLY;COUNT=3", "20260131T100000", "20260101T000000", "20261231T000000");
+        assert_eq!(occurrences, ["20260131T100000", "20260331T100000", "20260531T100000"]);
+    }
+
+    #[test]
+    fn monthly_rule_uses_month_days_from_end() {
+        let occurrences = get_occurrences(
+            "FREQ=MONTHLY;BYMONTHDAY=-1",
+            "20260131T100000",
+            "20260101T000000",
+            "20260401T000000",
+        );
+        assert_eq!(occurrences, ["20260131T100000", "20260228T100000", "20260331T100000"]);
+    }
+
+    #[test]
+    fn monthly_rule_uses_weekday_positions() {
+        let occurrences = get_occurrences("FREQ=MONTHLY;BYDAY=2TU", "20261013T090000", "20261001T000000", "20261231T000000");
+        assert_eq!(occurrences, ["20261013T090000", "20261110T090000", "20261208T090000"]);
+    }
+
+    #[test]
+    fn yearly_rule_skips_missing_dates() {
+        let occurrences = get_occurrences("FREQ=YEARLY", "20240229T090000", "20240101T000000", "20300101T000000");
+        assert_eq!(occurrences, ["20240229T090000", "20280229T090000"]);
+    }
+
+    #[test]
+    fn unsupported_rules_are_rejected() {
+        let parse = |x: &str| Rule::parse(x, |_| None).err();
+        assert_eq!(parse("FREQ=YEARLY;BYMONTH=10").as_deref(), Some("unsupported rule part BYMONTH"));
+        assert_eq!(
+            parse("FREQ=MONTHLY;BYDAY=MO;BYSETPOS=-1").as_deref(),
+            Some("unsupported rule part BYSETPOS")
+        );
+        assert_eq!(parse("FREQ=HOURLY").as_deref(), Some("unsupported frequency HOURLY"));
+        assert!(parse("FREQ=WEEKLY;BYDAY=1MO").is_some());
+        assert!(parse("INTERVAL=2").is_some());
+        assert_eq!(parse("FREQ=WEEKLY;BYDAY=ÜT").as_deref(), Some("invalid weekday ÜT"));
+        assert_eq!(parse("FREQ=MONTHLY;BYDAY=1ÖM").as_deref(), Some("invalid weekday 1ÖM"));
+    }
+
+    #[test]
+    fn old_rules_skip_to_range() {
+        let occurrences = get_occurrences("FREQ=DAILY;INTERVAL=3", "15000101T090000", "20261019T000000", "20261025T000000");
+        assert_eq!(occurrences, ["20261021T090000", "20261024T090000"]);
+
+        let occurrences = get_occurrences(
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH",
+            "20261020T090000",
+            "20261104T000000",
+            "20261120T000000",
+        );
+        assert_eq!(occurrences, ["20261105T090000", "20261117T090000", "20261119T090000"]);
+
+        let occurrences = get_occurrences("FREQ=MONTHLY;BYDAY=2TU", "20261013T090000", "20271101T000000", "20271231T000000");
+        assert_eq!(occurrences, ["20271109T090000", "20271214T090000"]);
+    }
+}
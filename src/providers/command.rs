@@ -0,0 +1,274 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::mpsc,
+};
+
+use crate::config::{CommandMode, CommandProviderConfig, PayloadFormat};
+
+use super::_base::{Provider, ProviderContext, ProviderResult};
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let value = value.trim();
+    let result = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(digits) => u8::from_str_radix(digits, 16),
+        None => value.parse::<u8>(),
+    };
+    return result.map_err(|_| format!("invalid byte {:?}", value));
+}
+
+fn get_json_payload(value: &serde_json::Value, format: PayloadFormat) -> Result<Vec<u8>, String> {
+    return match (format, value) {
+        (PayloadFormat::Text, serde_json::Value::String(x)) => Ok(x.clone().into_bytes()),
+        (PayloadFormat::Text, x) => Ok(x.to_string().into_bytes()),
+        (PayloadFormat::Bytes, serde_json::Value::Array(x)) => x.iter().map(get_json_byte).collect(),
+        (PayloadFormat::Bytes, x) => get_json_byte(x).map(|x| vec![x]),
+    };
+}
+
+fn get_json_byte(value: &serde_json::Value) -> Result<u8, String> {
+    return match value {
+        serde_json::Value::Number(x) => x.as_u64().and_then(|x| u8::try_from(x).ok()).ok_or(format!("invalid byte {}", x)),
+        serde_json::Value::String(x) => parse_byte(x),
+        x => Err(format!("invalid byte {}", x)),
+    };
+}
+
+/// Converts a line of command output to message payload according to config
+fn get_payload(line: &str, config: &CommandProviderConfig) -> Result<Vec<u8>, String> {
+    let mut payload = if let Some(pointer) = &config.json_pointer {
+        let json = serde_json::from_str::<serde_json::Value>(line).map_err(|e| format!("invalid JSON: {}", e))?;
+        let value = json.pointer(pointer).ok_or(format!("{} not found", pointer))?;
+        get_json_payload(value, config.payload)?
+    } else {
+        match config.payload {
+            PayloadFormat::Text => line.to_string().into_bytes(),
+            PayloadFormat::Bytes => line
+                .split(|x: char| x == ',' || x.is_whitespace())
+                .filter(|x| !x.is_empty())
+                .map(parse_byte)
+                .collect::<Result<Vec<u8>, String>>()?,
+        }
+    };
+
+    payload.truncate(config.max_length);
+    if config.payload == PayloadFormat::Text {
+        payload.insert(0, payload.len() as u8);
+    }
+
+    return Ok(payload);
+}
+
+pub struct CommandProvider {
+    config: CommandProviderConfig,
+}
+
+impl CommandProvider {
+    pub fn new(config: CommandProviderConfig) -> Box<dyn Provider> {
+        let provider = CommandProvider { config };
+        return Box::new(provider);
+    }
+
+    fn create_command(&self) -> Command {
+        let mut command = Command::new(&self.config.command);
+        command.args(&self.config.args).stdin(Stdio::null()).kill_on_drop(true);
+        return command;
+    }
+
+    fn parse_line(&self, line: &str) -> Option<Vec<u8>> {
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        return get_payload(line, &self.config)
+            .map_err(|e| tracing::warn!("{} Provider can not parse line {:?}: {}", self.name(), line, e))
+            .ok();
+    }
+
+    fn send_data(&self, payload: &[u8], data_sender: &mpsc::Sender<Vec<u8>>) {
+        let mut data = payload.to_vec();
+        data.insert(0, self.config.message_type);
+        data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+    }
+
+    /// Output is sent only when it differs from the previous run, command runs again right after system resume.
+    /// Command running longer than `timeout` is killed on drop
+    async fn run_periodic(&self, ctx: &ProviderContext) -> ProviderResult {
+        let mut resume_receiver = ctx.resume_sender.subscribe();
+        let mut last_payloads: Vec<Vec<u8>> = vec![];
+        let timeout = Duration::from_millis(self.config.timeout);
+        loop {
+            let result = tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                x = tokio::time::timeout(timeout, self.create_command().output()) => x,
+            };
+
+            match result {
+                Ok(output) => {
+                    let output = output.map_err(|e| format!("Can not run {}: {}", self.config.command, e))?;
+                    if output.status.success() {
+                        let payloads = String::from_utf8_lossy(&output.stdout)
+                            .lines()
+                            .filter_map(|x| self.parse_line(x))
+                            .collect::<Vec<_>>();
+                        if payloads != last_payloads {
+                            payloads.iter().for_each(|x| self.send_data(x, &ctx.data_sender));
+                            last_payloads = payloads;
+                        }
+                    } else {
+                        tracing::warn!("{} Provider command failed with {}", self.name(), output.status);
+                    }
+                }
+                Err(_) => tracing::warn!("{} Provider command timed out after {} ms", self.name(), self.config.timeout),
+            }
+
+            tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(Duration::from_millis(self.config.interval)) => (),
+                _ = resume_receiver.recv() => (),
+            }
+        }
+
+        return Ok(());
+    }
+
+    async fn run_stream(&self, ctx: &ProviderContext) -> ProviderResult {
+        let mut child = self
+            .create_command()
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Can not run {}: {}", self.config.command, e))?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        loop {
+            tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                line = lines.next_line() => match line.map_err(|e| e.to_string())? {
+                    Some(line) => {
+                        if let Some(payload) = self.parse_line(&line) {
+                            self.send_data(&payload, &ctx.data_sender);
+                        }
+                    }
+                    None => {
+                        let status = child.wait().await.map_err(|e| e.to_string())?;
+                        return Err(format!("{} exited with {}", self.config.command, status));
+                    }
+                },
+            }
+        }
+
+        let _ = child.kill().await;
+        return Ok(());
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for CommandProvider {
+    fn name(&self) -> &str {
+        return &self.config.name;
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        return match self.config.mode {
+            CommandMode::Periodic => self.run_periodic(&ctx).await,
+            CommandMode::Stream => self.run_stream(&ctx).await,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+
+    fn create_config(config: serde_json::Value) -> CommandProviderConfig {
+        let mut value = serde_json::json!({ "name": "Test", "command": "true", "messageType": 0xD0 });
+        value.as_object_mut().unwrap().extend(config.as_object().unwrap().clone());
+        return serde_json::from_value(value).unwrap();
+    }
+
+    fn get_line_payload(line: &str, config: serde_json::Value) -> Result<Vec<u8>, String> {
+        return get_payload(line, &create_config(config));
+    }
+
+    #[test]
+    fn byte_accepts_decimal_and_hex() {
+        assert_eq!(parse_byte("42"), Ok(42));
+        assert_eq!(parse_byte(" 0x2A "), Ok(42));
+        assert_eq!(parse_byte("0XfF"), Ok(255));
+        assert!(parse_byte("256").is_err());
+        assert!(parse_byte("0x100").is_err());
+        assert!(parse_byte("-1").is_err());
+        assert!(parse_byte("0x").is_err());
+    }
+
+    #[test]
+    fn text_payload_starts_with_length() {
+        assert_eq!(get_line_payload("ok", serde_json::json!({})), Ok(vec![2, b'o', b'k']));
+        assert_eq!(
+            get_line_payload("passed", serde_json::json!({ "maxLength": 4 })),
+            Ok(vec![4, b'p', b'a', b's', b's'])
+        );
+    }
+
+    #[test]
+    fn bytes_payload_is_split_by_commas_and_spaces() {
+        let config = serde_json::json!({ "payload": "bytes", "maxLength": 3 });
+        assert_eq!(get_line_payload("1, 0x02  3,4", config.clone()), Ok(vec![1, 2, 3]));
+        assert!(get_line_payload("1, 300", config).is_err());
+    }
+
+    #[test]
+    fn json_pointer_selects_value() {
+        let text = serde_json::json!({ "jsonPointer": "/build/status" });
+        assert_eq!(
+            get_line_payload(r#"{"build":{"status":"ok"}}"#, text.clone()),
+            Ok(vec![2, b'o', b'k'])
+        );
+        assert_eq!(
+            get_line_payload(r#"{"build":{"status":12}}"#, text.clone()),
+            Ok(vec![2, b'1', b'2'])
+        );
+        assert!(get_line_payload(r#"{"build":{}}"#, text.clone()).is_err());
+        assert!(get_line_payload("not json", text).is_err());
+
+        let bytes = serde_json::json!({ "jsonPointer": "/value", "payload": "bytes" });
+        assert_eq!(get_line_payload(r#"{"value":[1,"0x10",255]}"#, bytes.clone()), Ok(vec![1, 16, 255]));
+        assert_eq!(get_line_payload(r#"{"value":"0xAB"}"#, bytes.clone()), Ok(vec![0xAB]));
+        assert!(get_line_payload(r#"{"value":[256]}"#, bytes.clone()).is_err());
+        assert!(get_line_payload(r#"{"value":[true]}"#, bytes).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn periodic_command_is_killed_after_timeout() {
+        // First run hangs and is killed, the next one prints immediately
+        let marker = std::env::temp_dir().join(format!("qmk-hid-host-command-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!(
+            "if [ -f '{0}' ]; then echo done; else touch '{0}'; exec sleep 10; fi",
+            marker.display()
+        );
+        let config = create_config(serde_json::json!({ "command": "sh", "args": ["-c", script], "interval": 10, "timeout": 200 }));
+
+        let (data_sender, mut data_receiver) = mpsc::channel(10);
+        let ctx = ProviderContext {
+            data_sender,
+            keyboard_receiver: broadcast::channel(1).1,
+            resume_sender: broadcast::channel(1).0,
+            cancellation_token: CancellationToken::new(),
+        };
+        let cancellation_token = ctx.cancellation_token.clone();
+        let provider = CommandProvider::new(config);
+        let task = tokio::spawn(async move { provider.run(ctx).await });
+
+        let data = tokio::time::timeout(Duration::from_secs(5), data_receiver.recv()).await;
+        cancellation_token.cancel();
+        let _ = std::fs::remove_file(&marker);
+        assert_eq!(data.unwrap(), Some(vec![0xD0, 4, b'd', b'o', b'n', b'e']));
+        assert_eq!(task.await.unwrap(), Ok(()));
+    }
+}
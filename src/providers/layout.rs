@@ -14,4 +14,4 @@ pub use self::windows::LayoutProvider;
 mod macos;
 
 #[cfg(target_os = "macos")]
-pub use self::macos::LayoutProvider;
\ No newline at end of file
+pub use self::macos::LayoutProvider;
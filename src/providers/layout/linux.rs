@@ -1,21 +1,32 @@
 use std::{ffi, mem, ptr};
 
-use crate::data_type::DataType;
-use tokio::sync::{broadcast, mpsc};
-use x11::xlib::{XGetAtomName, XOpenDisplay, XkbAllocKeyboard, XkbGetNames, XkbGetState, _XDisplay, _XkbDesc, _XkbStateRec};
+use crate::{config::LayoutProviderConfig, data_type::DataType};
+use tokio::sync::mpsc;
+use x11::xlib::{_XDisplay, _XkbDesc, _XkbStateRec, XCloseDisplay, XGetAtomName, XOpenDisplay, XkbAllocKeyboard, XkbGetNames, XkbGetState};
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+
+fn get_symbols(display: *mut _XDisplay, keyboard: *mut _XkbDesc) -> Result<String, String> {
+    if keyboard.is_null() {
+        return Err("Can not allocate XKB keyboard".to_string());
+    }
+
+    if unsafe { XkbGetNames(display, 1 << 2, keyboard) } != 0 || unsafe { keyboard.read().names.is_null() } {
+        return Err("Can not get XKB symbols names".to_string());
+    }
 
-fn get_symbols(display: *mut _XDisplay, keyboard: *mut _XkbDesc) -> String {
-    unsafe { XkbGetNames(display, 1 << 2, keyboard) };
     let symbols_atom = unsafe { keyboard.read().names.read().symbols };
     let symbols_ptr = unsafe { XGetAtomName(display, symbols_atom) };
+    if symbols_ptr.is_null() {
+        return Err("Can not get XKB symbols atom name".to_string());
+    }
+
     let symbols_cstr = unsafe { ffi::CStr::from_ptr(symbols_ptr) };
     let symbols = String::from_utf8(symbols_cstr.to_bytes().to_vec()).unwrap_or_default();
 
     tracing::info!("layout symbols: {}", symbols);
 
-    return symbols;
+    return Ok(symbols);
 }
 
 fn get_layout_index(display: *mut _XDisplay) -> usize {
@@ -33,56 +44,58 @@ fn send_data(value: &String, layouts: &Vec<String>, data_sender: &mpsc::Sender<V
     }
 }
 
+fn watch_layout(display: *mut _XDisplay, layouts: &Vec<String>, poll_interval: u64, ctx: &ProviderContext) -> ProviderResult {
+    let mut synced_layout = 0;
+    let keyboard = unsafe { XkbAllocKeyboard() };
+    let symbols = get_symbols(display, keyboard)?;
+    let symbol_list = symbols.split('+').map(|x| x.to_string()).collect::<Vec<String>>();
+
+    while !ctx.cancellation_token.is_cancelled() {
+        let layout = get_layout_index(display);
+        if synced_layout != layout {
+            synced_layout = layout;
+            let layout_symbol = symbol_list.get(layout + 1).map(|x| x.to_string()).unwrap_or_default();
+            let layout_name = layout_symbol.split([':', '(']).next().unwrap_or_default().to_string();
+            send_data(&layout_name, layouts, &ctx.data_sender);
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(poll_interval));
+    }
+
+    return Ok(());
+}
+
 pub struct LayoutProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
-    layouts: Vec<String>,
+    config: LayoutProviderConfig,
 }
 
 impl LayoutProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>, layouts: Vec<String>) -> Box<dyn Provider> {
-        let provider = LayoutProvider {
-            data_sender,
-            connected_sender,
-            layouts,
-        };
+    pub fn new(config: LayoutProviderConfig) -> Box<dyn Provider> {
+        let provider = LayoutProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for LayoutProvider {
-    fn start(&self) {
-        tracing::info!("Layout Provider started");
+    fn name(&self) -> &str {
+        return "Layout";
+    }
 
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        let layouts = self.layouts.clone();
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let layouts = self.config.layouts.clone();
+        let poll_interval = self.config.poll_interval;
 
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
-            let mut synced_layout = 0;
+        return run_blocking(move || {
             let display = unsafe { XOpenDisplay(ptr::null()) };
-            let keyboard = unsafe { XkbAllocKeyboard() };
-            let symbols = get_symbols(display, keyboard);
-            let symbol_list = symbols.split('+').map(|x| x.to_string()).collect::<Vec<String>>();
-
-            loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    break;
-                }
-
-                let layout = get_layout_index(display);
-                if synced_layout != layout {
-                    synced_layout = layout;
-                    let layout_symbol = symbol_list.get(layout + 1).map(|x| x.to_string()).unwrap_or_default();
-                    let layout_name = layout_symbol.split([':', '(']).next().unwrap_or_default().to_string();
-                    send_data(&layout_name, &layouts, &data_sender);
-                }
-
-                std::thread::sleep(std::time::Duration::from_millis(100));
+            if display.is_null() {
+                return Err("Can not open X display".to_string());
             }
 
-            tracing::info!("Layout Provider stopped");
-        });
+            let result = watch_layout(display, &layouts, poll_interval, &ctx);
+            unsafe { XCloseDisplay(display) };
+            return result;
+        })
+        .await;
     }
 }
@@ -1,16 +1,16 @@
+use crate::config::LayoutProviderConfig;
 use crate::data_type::DataType;
 use core_foundation::base::{CFRelease, TCFType};
 use core_foundation::string::{CFString, CFStringRef};
 use libc::c_void;
-use std::sync::{Arc, Mutex};
-use tokio::sync::{broadcast, mpsc};
-use std::thread;
-use std::time::Duration;
 use objc2::runtime::{AnyObject, NSObject, Sel};
 use objc2::{class, msg_send, sel};
 use objc2_foundation::NSString;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc;
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
 use std::collections::HashMap;
 
 fn create_layout_map() -> HashMap<&'static str, &'static str> {
@@ -120,62 +120,38 @@ fn send_data(value: &String, layouts: &Vec<String>, data_sender: &mpsc::Sender<V
 }
 
 pub struct LayoutProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
-    layouts: Vec<String>,
+    config: LayoutProviderConfig,
 }
 
 impl LayoutProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>, layouts: Vec<String>) -> Box<dyn Provider> {
-        let provider = LayoutProvider {
-            data_sender,
-            connected_sender,
-            layouts,
-        };
+    pub fn new(config: LayoutProviderConfig) -> Box<dyn Provider> {
+        let provider = LayoutProvider { config };
         Box::new(provider)
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for LayoutProvider {
-    fn start(&self) {
-        tracing::info!("Layout Provider started");
+    fn name(&self) -> &str {
+        "Layout"
+    }
 
-        let data_sender = self.data_sender.clone();
-        let layouts = self.layouts.clone();
-        let connected_sender = self.connected_sender.clone();
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let layouts = self.config.layouts.clone();
+        let poll_interval = self.config.poll_interval;
         let layout_map = create_layout_map(); // Создаём маппинг для раскладок
         let mut synced_layout = "".to_string();
 
-        let is_connected = Arc::new(Mutex::new(true));
-        let is_connected_ref = is_connected.clone();
-
         // Запускаем провайдера в отдельном потоке
-        std::thread::spawn(move || {
-            // Поток для отслеживания подключения/отключения
-            let mut connected_receiver = connected_sender.subscribe();
-            std::thread::spawn(move || {
-                loop {
-                    if !connected_receiver.try_recv().unwrap_or(true) {
-                        let mut is_connected = is_connected_ref.lock().unwrap();
-                        *is_connected = false;
-                        break;
-                    }
-                    thread::sleep(Duration::from_millis(100));
-                }
-            });
-
+        run_blocking(move || {
             // Основной цикл для проверки раскладки клавиатуры
-            loop {
-                if !*(is_connected.lock().unwrap()) {
-                    break;
-                }
-
+            while !ctx.cancellation_token.is_cancelled() {
                 // Получаем текущую раскладку
                 if let Some(layout) = get_keyboard_layout() {
                     if let Some(layout_code) = get_keyboard_layout_code(&layout, &layout_map) {
                         if synced_layout != layout_code {
                             synced_layout = layout_code.clone();
-                            send_data(&synced_layout, &layouts, &data_sender);
+                            send_data(&synced_layout, &layouts, &ctx.data_sender);
                         }
                     } else {
                         tracing::warn!("Unknown layout: {}", layout);
@@ -183,10 +159,11 @@ impl Provider for LayoutProvider {
                 }
 
                 // Ожидание перед следующей проверкой
-                thread::sleep(Duration::from_millis(500)); // Опрос каждые 500 мс
+                thread::sleep(Duration::from_millis(poll_interval));
             }
 
-            tracing::info!("Layout Provider stopped");
-        });
+            Ok(())
+        })
+        .await
     }
 }
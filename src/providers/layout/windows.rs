@@ -1,4 +1,4 @@
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
 use windows::Win32::{
     Globalization::{GetLocaleInfoW, LOCALE_SISO639LANGNAME},
     UI::{
@@ -8,9 +8,9 @@ use windows::Win32::{
     },
 };
 
-use crate::data_type::DataType;
+use crate::{config::LayoutProviderConfig, data_type::DataType};
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
 
 unsafe fn get_layout() -> Option<String> {
     let focused_window = GetForegroundWindow();
@@ -34,47 +34,40 @@ fn send_data(value: &String, layouts: &Vec<String>, data_sender: &mpsc::Sender<V
 }
 
 pub struct LayoutProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
-    layouts: Vec<String>,
+    config: LayoutProviderConfig,
 }
 
 impl LayoutProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>, layouts: Vec<String>) -> Box<dyn Provider> {
-        let provider = LayoutProvider {
-            data_sender,
-            connected_sender,
-            layouts,
-        };
+    pub fn new(config: LayoutProviderConfig) -> Box<dyn Provider> {
+        let provider = LayoutProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for LayoutProvider {
-    fn start(&self) {
-        tracing::info!("Layout Provider started");
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        let layouts = self.layouts.clone();
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
-            let mut synced_layout = "".to_string();
-            loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    break;
-                }
+    fn name(&self) -> &str {
+        return "Layout";
+    }
 
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let layouts = self.config.layouts.clone();
+        let poll_interval = self.config.poll_interval;
+        return run_blocking(move || {
+            let mut synced_layout = "".to_string();
+            while !ctx.cancellation_token.is_cancelled() {
                 if let Some(layout) = unsafe { get_layout() } {
                     if synced_layout != layout {
                         synced_layout = layout;
-                        send_data(&synced_layout, &layouts, &data_sender);
+                        send_data(&synced_layout, &layouts, &ctx.data_sender);
                     }
                 }
 
-                std::thread::sleep(std::time::Duration::from_millis(100));
+                std::thread::sleep(std::time::Duration::from_millis(poll_interval));
             }
 
-            tracing::info!("Layout Provider stopped");
-        });
+            return Ok(());
+        })
+        .await;
     }
 }
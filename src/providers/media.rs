@@ -14,4 +14,4 @@ pub use self::windows::MediaProvider;
 mod macos;
 
 #[cfg(target_os = "macos")]
-pub use self::macos::MediaProvider;
\ No newline at end of file
+pub use self::macos::MediaProvider;
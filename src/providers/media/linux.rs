@@ -1,104 +1,101 @@
-use mpris::{Metadata, PlayerFinder};
-use tokio::sync::{broadcast, mpsc};
+use mpris::{Metadata, Player, PlayerFinder};
+use tokio::sync::mpsc;
 
-use crate::data_type::DataType;
+use crate::{config::MediaProviderConfig, data_type::DataType};
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
 
-fn send_media_data(metadata: &Metadata, data_sender: &mpsc::Sender<Vec<u8>>, current: &(String, String)) -> (String, String) {
+const PROGRESS_INTERVAL: u32 = 500;
+
+fn find_player(config: &MediaProviderConfig) -> Option<Player> {
+    let finder = PlayerFinder::new().ok()?;
+    if config.players.is_empty() {
+        return finder.find_active().ok();
+    }
+
+    return finder.find_all().ok()?.into_iter().find(|x| config.is_player_allowed(x.identity()));
+}
+
+fn send_media_data(
+    metadata: &Metadata,
+    data_sender: &mpsc::Sender<Vec<u8>>,
+    current: &(String, String),
+    max_length: usize,
+) -> (String, String) {
     let (mut artist, mut title) = current.clone();
 
     let new_artist = metadata.artists().and_then(|x| x.get(0).map(|x| x.to_string())).unwrap_or_default();
     if !new_artist.is_empty() && artist != new_artist {
         tracing::info!("new artist: {}", new_artist);
         artist = new_artist;
-        send_data(DataType::MediaArtist, &artist, &data_sender);
+        send_data(DataType::MediaArtist, &artist, max_length, &data_sender);
     }
 
     let new_title = metadata.title().unwrap_or_default().to_string();
     if !new_title.is_empty() && title != new_title {
         tracing::info!("new title: {}", new_title);
         title = new_title;
-        send_data(DataType::MediaTitle, &title, &data_sender);
+        send_data(DataType::MediaTitle, &title, max_length, &data_sender);
         std::thread::sleep(std::time::Duration::from_millis(50));
     }
 
     return (artist, title);
 }
 
-fn send_data(data_type: DataType, value: &String, data_sender: &mpsc::Sender<Vec<u8>>) {
+fn send_data(data_type: DataType, value: &String, max_length: usize, data_sender: &mpsc::Sender<Vec<u8>>) {
     let mut data = value.to_string().into_bytes();
-    data.truncate(30);
+    data.truncate(max_length);
     data.insert(0, data.len() as u8);
     data.insert(0, data_type as u8);
     data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
 }
 
 pub struct MediaProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: MediaProviderConfig,
 }
 
 impl MediaProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = MediaProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: MediaProviderConfig) -> Box<dyn Provider> {
+        let provider = MediaProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for MediaProvider {
-    fn start(&self) {
-        tracing::info!("Media Provider started");
-
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
+    fn name(&self) -> &str {
+        return "Media";
+    }
 
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let config = self.config.clone();
+        return run_blocking(move || {
             let mut media_data = (String::default(), String::default());
 
-            'outer: loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    break;
-                }
-
-                if let Ok(Ok(player)) = PlayerFinder::new().map(|x| x.find_active()) {
-                    if let Ok(metadata) = player.get_metadata() {
-                        media_data = send_media_data(&metadata, &data_sender, &media_data);
-                    }
-
-                    if let Ok(events) = player.events() {
-                        for event in events {
-                            tracing::debug!("{:?}", event);
-
-                            if !connected_receiver.try_recv().unwrap_or(true) {
-                                break 'outer;
+            while !ctx.cancellation_token.is_cancelled() {
+                if let Some(player) = find_player(&config) {
+                    // progress tracker wakes up on every player event or after the interval, which allows to check for cancellation
+                    if let Ok(mut tracker) = player.track_progress(PROGRESS_INTERVAL) {
+                        while !ctx.cancellation_token.is_cancelled() {
+                            let tick = tracker.tick();
+                            if tick.player_quit {
+                                break;
                             }
 
-                            match event {
-                                Ok(mpris::Event::Playing) => {
-                                    if let Ok(metadata) = player.get_metadata() {
-                                        media_data = send_media_data(&metadata, &data_sender, &media_data);
-                                    }
-                                }
-                                Ok(mpris::Event::TrackChanged(metadata)) => {
-                                    media_data = send_media_data(&metadata, &data_sender, &media_data);
-                                }
-                                _ => (),
-                            }
+                            media_data = send_media_data(tick.progress.metadata(), &ctx.data_sender, &media_data, config.max_length);
                         }
+
+                        continue;
                     }
                 }
 
                 tracing::info!("waiting for player...");
 
-                std::thread::sleep(std::time::Duration::from_millis(1000));
+                std::thread::sleep(std::time::Duration::from_millis(config.poll_interval));
             }
 
-            tracing::info!("Media Provider stopped");
-        });
+            return Ok(());
+        })
+        .await;
     }
 }
@@ -1,41 +1,96 @@
-use objc2::runtime::AnyObject;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use crate::config::MediaProviderConfig;
+use crate::data_type::DataType;
 use objc2::rc::{autoreleasepool, AutoreleasePool, Retained};
+use objc2::runtime::AnyObject;
 use objc2::{msg_send, ClassType};
-use objc2_foundation::{ns_string, NSString, NSDictionary};
+use objc2_foundation::{ns_string, NSDictionary, NSString};
 use objc2_media_player::MPNowPlayingInfoCenter;
-use tokio::sync::{broadcast, mpsc};
-use crate::data_type::DataType;
-use super::super::_base::Provider;
 use std::sync::atomic::{AtomicBool, Ordering};
-use translit::{Transliterator, CharsMapping};
+use tokio::sync::mpsc;
+use translit::{CharsMapping, Transliterator};
 
 // Определяем таблицу для транслитерации русского алфавита в латиницу
 fn get_transliteration_table() -> CharsMapping {
     [
         // Строчные буквы
-        ("а", "a"), ("б", "b"), ("в", "v"), ("г", "g"), ("д", "d"),
-        ("е", "e"), ("ё", "yo"), ("ж", "zh"), ("з", "z"), ("и", "i"),
-        ("й", "y"), ("к", "k"), ("л", "l"), ("м", "m"), ("н", "n"),
-        ("о", "o"), ("п", "p"), ("р", "r"), ("с", "s"), ("т", "t"),
-        ("у", "u"), ("ф", "f"), ("х", "kh"), ("ц", "ts"), ("ч", "ch"),
-        ("ш", "sh"), ("щ", "sch"), ("ъ", ""), ("ы", "y"), ("ь", ""),
-        ("э", "e"), ("ю", "yu"), ("я", "ya"),
+        ("а", "a"),
+        ("б", "b"),
+        ("в", "v"),
+        ("г", "g"),
+        ("д", "d"),
+        ("е", "e"),
+        ("ё", "yo"),
+        ("ж", "zh"),
+        ("з", "z"),
+        ("и", "i"),
+        ("й", "y"),
+        ("к", "k"),
+        ("л", "l"),
+        ("м", "m"),
+        ("н", "n"),
+        ("о", "o"),
+        ("п", "p"),
+        ("р", "r"),
+        ("с", "s"),
+        ("т", "t"),
+        ("у", "u"),
+        ("ф", "f"),
+        ("х", "kh"),
+        ("ц", "ts"),
+        ("ч", "ch"),
+        ("ш", "sh"),
+        ("щ", "sch"),
+        ("ъ", ""),
+        ("ы", "y"),
+        ("ь", ""),
+        ("э", "e"),
+        ("ю", "yu"),
+        ("я", "ya"),
         // Заглавные буквы
-        ("А", "A"), ("Б", "B"), ("В", "V"), ("Г", "G"), ("Д", "D"),
-        ("Е", "E"), ("Ё", "Yo"), ("Ж", "Zh"), ("З", "Z"), ("И", "I"),
-        ("Й", "Y"), ("К", "K"), ("Л", "L"), ("М", "M"), ("Н", "N"),
-        ("О", "O"), ("П", "P"), ("Р", "R"), ("С", "S"), ("Т", "T"),
-        ("У", "U"), ("Ф", "F"), ("Х", "Kh"), ("Ц", "Ts"), ("Ч", "Ch"),
-        ("Ш", "Sh"), ("Щ", "Sch"), ("Ъ", ""), ("Ы", "Y"), ("Ь", ""),
-        ("Э", "E"), ("Ю", "Yu"), ("Я", "Ya")
-    ].iter().cloned().collect()
+        ("А", "A"),
+        ("Б", "B"),
+        ("В", "V"),
+        ("Г", "G"),
+        ("Д", "D"),
+        ("Е", "E"),
+        ("Ё", "Yo"),
+        ("Ж", "Zh"),
+        ("З", "Z"),
+        ("И", "I"),
+        ("Й", "Y"),
+        ("К", "K"),
+        ("Л", "L"),
+        ("М", "M"),
+        ("Н", "N"),
+        ("О", "O"),
+        ("П", "P"),
+        ("Р", "R"),
+        ("С", "S"),
+        ("Т", "T"),
+        ("У", "U"),
+        ("Ф", "F"),
+        ("Х", "Kh"),
+        ("Ц", "Ts"),
+        ("Ч", "Ch"),
+        ("Ш", "Sh"),
+        ("Щ", "Sch"),
+        ("Ъ", ""),
+        ("Ы", "Y"),
+        ("Ь", ""),
+        ("Э", "E"),
+        ("Ю", "Yu"),
+        ("Я", "Ya"),
+    ]
+    .iter()
+    .cloned()
+    .collect()
 }
 
-
 fn transliterate_text(text: &str) -> String {
-    let table = get_transliteration_table();  // Получаем таблицу транслитерации
-    let transliterator = Transliterator::new(table);  // Создаем объект Transliterator с маппингом
-    let result = transliterator.convert(text, false);  // Применяем транслитерацию
+    let table = get_transliteration_table(); // Получаем таблицу транслитерации
+    let transliterator = Transliterator::new(table); // Создаем объект Transliterator с маппингом
+    let result = transliterator.convert(text, false); // Применяем транслитерацию
     result
 }
 // Для отслеживания переключения между методами
@@ -44,7 +99,7 @@ static USE_APPLE_SCRIPT: AtomicBool = AtomicBool::new(false);
 // Функция для выполнения AppleScript через Scripting Bridge
 fn execute_applescript(script: &str) -> Option<String> {
     use std::process::Command;
-    tracing::debug!("Executing AppleScript: {}", script);  // Лог выполнения AppleScript
+    tracing::debug!("Executing AppleScript: {}", script); // Лог выполнения AppleScript
     match Command::new("osascript").arg("-e").arg(script).output() {
         Ok(output) if output.status.success() => {
             let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -54,11 +109,15 @@ fn execute_applescript(script: &str) -> Option<String> {
             } else {
                 Some(result)
             }
-        },
+        }
         Ok(output) => {
-            tracing::error!("AppleScript failed with status: {}. Stderr: {}", output.status, String::from_utf8_lossy(&output.stderr));
+            tracing::error!(
+                "AppleScript failed with status: {}. Stderr: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
             None
-        },
+        }
         Err(e) => {
             tracing::error!("Failed to execute AppleScript: {}", e);
             None
@@ -66,7 +125,6 @@ fn execute_applescript(script: &str) -> Option<String> {
     }
 }
 
-
 // Получаем информацию о текущем треке через AppleScript
 fn get_now_playing_via_applescript() -> Option<(String, String)> {
     let script = r#"
@@ -156,7 +214,8 @@ fn send_media_data(
     title: &Option<String>,
     data_sender: &mpsc::Sender<Vec<u8>>,
     last_artist: &mut String,
-    last_title: &mut String
+    last_title: &mut String,
+    max_length: usize,
 ) {
     let mut updated = false;
 
@@ -165,14 +224,14 @@ fn send_media_data(
         let artist_transliterated = transliterate_text(new_artist);
         if artist_transliterated != *last_artist {
             tracing::info!("Sending new artist (transliterated): {}", artist_transliterated);
-            send_data(DataType::MediaArtist, &artist_transliterated, data_sender);
+            send_data(DataType::MediaArtist, &artist_transliterated, max_length, data_sender);
             *last_artist = artist_transliterated;
             updated = true;
         }
     } else if !last_artist.is_empty() {
         // Если информация об артисте отсутствует, отправляем пустую строку
         tracing::info!("Sending empty artist to clear display.");
-        send_data(DataType::MediaArtist, "", data_sender);
+        send_data(DataType::MediaArtist, "", max_length, data_sender);
         *last_artist = String::new();
         updated = true;
     }
@@ -182,14 +241,14 @@ fn send_media_data(
         let title_transliterated = transliterate_text(new_title);
         if title_transliterated != *last_title {
             tracing::info!("Sending new title (transliterated): {}", title_transliterated);
-            send_data(DataType::MediaTitle, &title_transliterated, data_sender);
+            send_data(DataType::MediaTitle, &title_transliterated, max_length, data_sender);
             *last_title = title_transliterated;
             updated = true;
         }
     } else if !last_title.is_empty() {
         // Если информация о треке отсутствует, отправляем пустую строку
         tracing::info!("Sending empty title to clear display.");
-        send_data(DataType::MediaTitle, "", data_sender);
+        send_data(DataType::MediaTitle, "", max_length, data_sender);
         *last_title = String::new();
         updated = true;
     }
@@ -200,11 +259,9 @@ fn send_media_data(
     }
 }
 
-
-
-fn send_data(data_type: DataType, value: &str, data_sender: &mpsc::Sender<Vec<u8>>) {
+fn send_data(data_type: DataType, value: &str, max_length: usize, data_sender: &mpsc::Sender<Vec<u8>>) {
     let mut data = value.as_bytes().to_vec();
-    // data.truncate(30);
+    data.truncate(max_length);
     data.insert(0, data.len() as u8);
     data.insert(0, data_type as u8);
 
@@ -217,48 +274,50 @@ fn send_data(data_type: DataType, value: &str, data_sender: &mpsc::Sender<Vec<u8
 }
 
 pub struct MediaProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: MediaProviderConfig,
 }
 
 impl MediaProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
+    pub fn new(config: MediaProviderConfig) -> Box<dyn Provider> {
         tracing::info!("MediaProvider is being initialized.");
 
-        let provider = MediaProvider {
-            data_sender,
-            connected_sender,
-        };
+        let provider = MediaProvider { config };
         Box::new(provider)
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for MediaProvider {
-    fn start(&self) {
+    fn name(&self) -> &str {
+        "Media"
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
         tracing::info!("Starting MediaProvider...");
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
+        let data_sender = ctx.data_sender.clone();
+        let config = self.config.clone();
 
-        std::thread::spawn(move || {
+        run_blocking(move || {
             tracing::debug!("Media Provider started thread.");
 
-            let mut connected_receiver = connected_sender.subscribe();
             let mut last_artist = String::new();
             let mut last_title = String::new();
 
-            loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    tracing::info!("Disconnected from sender.");
-                    break;
-                }
-
+            while !ctx.cancellation_token.is_cancelled() {
                 if USE_APPLE_SCRIPT.load(Ordering::Relaxed) {
                     if let Some((artist, title)) = get_now_playing_via_applescript() {
                         tracing::debug!("AppleScript retrieved info: {} - {}", artist, title);
-                        send_media_data(&Some(artist), &Some(title), &data_sender, &mut last_artist, &mut last_title);
+                        send_media_data(
+                            &Some(artist),
+                            &Some(title),
+                            &data_sender,
+                            &mut last_artist,
+                            &mut last_title,
+                            config.max_length,
+                        );
                     } else {
                         tracing::warn!("AppleScript failed, retrying after delay.");
-                        std::thread::sleep(std::time::Duration::from_secs(2));  // Добавляем небольшую задержку перед повтором
+                        std::thread::sleep(std::time::Duration::from_secs(2)); // Добавляем небольшую задержку перед повтором
                     }
                 } else {
                     autoreleasepool(|pool| unsafe {
@@ -270,7 +329,7 @@ impl Provider for MediaProvider {
                             } else {
                                 // Принудительно обновляем и артиста, и трек вместе
                                 if artist.is_some() && title.is_some() {
-                                    send_media_data(&artist, &title, &data_sender, &mut last_artist, &mut last_title);
+                                    send_media_data(&artist, &title, &data_sender, &mut last_artist, &mut last_title, config.max_length);
                                 } else {
                                     tracing::warn!("Incomplete media info (missing artist or title). Retrying...");
                                 }
@@ -283,11 +342,11 @@ impl Provider for MediaProvider {
                 }
 
                 // Увеличиваем задержку, чтобы убедиться, что данные собираются корректно
-                std::thread::sleep(std::time::Duration::from_secs(2));
+                std::thread::sleep(std::time::Duration::from_millis(config.poll_interval));
             }
 
-            tracing::info!("Media Provider stopped");
-        });
+            Ok(())
+        })
+        .await
     }
 }
-
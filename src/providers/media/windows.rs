@@ -1,43 +1,59 @@
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
 
 use windows::{
     Foundation::{EventRegistrationToken, TypedEventHandler},
     Media::Control::{GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager},
 };
 
-use crate::data_type::DataType;
+use crate::{config::MediaProviderConfig, data_type::DataType};
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
 
-fn get_manager() -> Result<GlobalSystemMediaTransportControlsSessionManager, ()> {
+fn get_manager() -> Result<GlobalSystemMediaTransportControlsSessionManager, String> {
     return GlobalSystemMediaTransportControlsSessionManager::RequestAsync()
         .and_then(|manager| manager.get())
-        .map_err(|e| tracing::error!("Can not get Session Manager: {}", e));
+        .map_err(|e| format!("Can not get Session Manager: {}", e));
+}
+
+fn get_session(
+    manager: &GlobalSystemMediaTransportControlsSessionManager,
+    config: &MediaProviderConfig,
+) -> Option<GlobalSystemMediaTransportControlsSession> {
+    if config.players.is_empty() {
+        return manager.GetCurrentSession().ok();
+    }
+
+    return manager
+        .GetSessions()
+        .ok()?
+        .into_iter()
+        .find(|x| config.is_player_allowed(&x.SourceAppUserModelId().unwrap_or_default().to_string()));
 }
 
 fn handle_session(
     session: &GlobalSystemMediaTransportControlsSession,
     data_sender: &mpsc::Sender<Vec<u8>>,
+    max_length: usize,
 ) -> Option<EventRegistrationToken> {
     let mut synced_artist = String::new();
     let mut synced_title = String::new();
     if let Some((artist, title)) = get_media_data(session) {
-        send_data(DataType::MediaArtist, &artist, &data_sender);
-        send_data(DataType::MediaTitle, &title, &data_sender);
+        send_data(DataType::MediaArtist, &artist, max_length, &data_sender);
+        send_data(DataType::MediaTitle, &title, max_length, &data_sender);
         synced_artist = artist;
         synced_title = title;
     }
 
     let data_sender = data_sender.clone();
     let session_handler = &TypedEventHandler::new(move |_session: &Option<GlobalSystemMediaTransportControlsSession>, _| {
-        if let Some((artist, title)) = get_media_data(_session.as_ref().unwrap()) {
+        if let Some((artist, title)) = _session.as_ref().and_then(get_media_data) {
             if synced_artist != artist {
-                send_data(DataType::MediaArtist, &artist, &data_sender);
+                send_data(DataType::MediaArtist, &artist, max_length, &data_sender);
                 synced_artist = artist;
             }
 
             if synced_title != title {
-                send_data(DataType::MediaTitle, &title, &data_sender);
+                send_data(DataType::MediaTitle, &title, max_length, &data_sender);
                 synced_title = title;
             }
         }
@@ -68,74 +84,65 @@ fn get_media_data(session: &GlobalSystemMediaTransportControlsSession) -> Option
     None
 }
 
-fn send_data(data_type: DataType, value: &String, data_sender: &mpsc::Sender<Vec<u8>>) {
+fn send_data(data_type: DataType, value: &String, max_length: usize, data_sender: &mpsc::Sender<Vec<u8>>) {
     let mut data = value.to_string().into_bytes();
-    data.truncate(30);
+    data.truncate(max_length);
     data.insert(0, data.len() as u8);
     data.insert(0, data_type as u8);
     data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
 }
 
 pub struct MediaProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: MediaProviderConfig,
 }
 
 impl MediaProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = MediaProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: MediaProviderConfig) -> Box<dyn Provider> {
+        let provider = MediaProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for MediaProvider {
-    fn start(&self) {
-        tracing::info!("Media Provider started");
+    fn name(&self) -> &str {
+        return "Media";
+    }
 
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let config = self.config.clone();
+        return run_blocking(move || {
+            let data_sender = ctx.data_sender.clone();
             let mut session_token: Option<EventRegistrationToken> = None;
 
-            if let Ok(manager) = get_manager() {
-                if let Some(session) = manager.GetCurrentSession().ok() {
-                    session_token = handle_session(&session, &data_sender);
-                }
-
-                let handler = TypedEventHandler::new(move |_manager: &Option<GlobalSystemMediaTransportControlsSessionManager>, _| {
-                    if let Some(session) = _manager.as_ref().unwrap().GetCurrentSession().ok() {
-                        if let Some(token) = session_token {
-                            let _ = session.RemoveMediaPropertiesChanged(token);
-                        }
-
-                        session_token = handle_session(&session, &data_sender);
-                    }
-
-                    Ok(())
-                });
-
-                let manager_token = manager
-                    .CurrentSessionChanged(&handler)
-                    .map_err(|e| tracing::error!("Can not register CurrentSessionChanged callback: {}", e));
+            let manager = get_manager()?;
+            if let Some(session) = get_session(&manager, &config) {
+                session_token = handle_session(&session, &data_sender, config.max_length);
+            }
 
-                loop {
-                    if !connected_receiver.try_recv().unwrap_or(true) {
-                        break;
+            let handler = TypedEventHandler::new(move |_manager: &Option<GlobalSystemMediaTransportControlsSessionManager>, _| {
+                if let Some(session) = _manager.as_ref().and_then(|x| get_session(x, &config)) {
+                    if let Some(token) = session_token {
+                        let _ = session.RemoveMediaPropertiesChanged(token);
                     }
 
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    session_token = handle_session(&session, &data_sender, config.max_length);
                 }
 
-                if let Ok(token) = manager_token {
-                    let _ = manager.RemoveCurrentSessionChanged(token);
-                }
+                Ok(())
+            });
+
+            let manager_token = manager
+                .CurrentSessionChanged(&handler)
+                .map_err(|e| format!("Can not register CurrentSessionChanged callback: {}", e))?;
 
-                tracing::info!("Media Provider stopped");
+            while !ctx.cancellation_token.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(100));
             }
-        });
+
+            let _ = manager.RemoveCurrentSessionChanged(manager_token);
+            return Ok(());
+        })
+        .await;
     }
 }
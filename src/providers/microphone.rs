@@ -0,0 +1,20 @@
+/// Command sent by keyboard as `[type, command]`
+const KEYBOARD_TOGGLE_MUTE: u8 = 0;
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::MicrophoneProvider;
+
+#[cfg(target_os = "windows")]
+mod windows;
+
+#[cfg(target_os = "windows")]
+pub use self::windows::MicrophoneProvider;
+
+#[cfg(target_os = "macos")]
+mod macos;
+
+#[cfg(target_os = "macos")]
+pub use self::macos::MicrophoneProvider;
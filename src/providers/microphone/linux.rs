@@ -0,0 +1,82 @@
+use libpulse_binding::context::introspect::Introspector;
+use pulsectl::controllers::types::DeviceInfo;
+use tokio::sync::mpsc;
+
+use crate::data_type::DataType;
+
+use super::super::_audio::{pulse, DeviceKind};
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::KEYBOARD_TOGGLE_MUTE;
+
+#[cfg(feature = "pipewire")]
+mod pipewire;
+
+fn is_toggle_mute(data: &[u8]) -> bool {
+    return data.first() == Some(&(DataType::Microphone as u8)) && data.get(1) == Some(&KEYBOARD_TOGGLE_MUTE);
+}
+
+/// Toggles default source mute, new state is sent after subscribe event
+fn toggle_mute(introspector: &mut Introspector, source: Option<&DeviceInfo>) -> Result<(), String> {
+    let source = source.ok_or("no default source")?;
+    introspector.set_source_mute_by_index(source.index, !source.mute, None);
+    return Ok(());
+}
+
+/// Sends volume if it differs from the last sent one
+fn send_data(value: (f32, bool), synced: &mut Option<(f32, bool)>, push_sender: &mpsc::Sender<Vec<u8>>) {
+    if *synced == Some(value) {
+        return;
+    }
+
+    *synced = Some(value);
+    let (volume, muted) = value;
+    let data = vec![DataType::Microphone as u8, (volume * 100.0).round() as u8, muted as u8];
+    push_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+}
+
+pub struct MicrophoneProvider {}
+
+impl MicrophoneProvider {
+    pub fn new() -> Box<dyn Provider> {
+        let provider = MicrophoneProvider {};
+        return Box::new(provider);
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for MicrophoneProvider {
+    fn name(&self) -> &str {
+        return "Microphone";
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        return run_blocking(move || {
+            // PipeWire without pipewire-pulse has no PulseAudio server to connect to
+            let handler = match pulse::connect("MicrophoneProvider") {
+                Ok(handler) => handler,
+                #[cfg(feature = "pipewire")]
+                Err(e) => {
+                    tracing::info!("{}, using PipeWire", e);
+                    return pipewire::run(ctx);
+                }
+                #[cfg(not(feature = "pipewire"))]
+                Err(e) => return Err(e),
+            };
+
+            let data_sender = ctx.data_sender.clone();
+            let mut synced = None;
+            return pulse::watch_default_device(
+                handler,
+                DeviceKind::Source,
+                ctx,
+                move |source| send_data(source.and_then(pulse::get_volume).unwrap_or_default(), &mut synced, &data_sender),
+                |introspector, source, data| {
+                    if is_toggle_mute(data) {
+                        toggle_mute(introspector, source).unwrap_or_else(|e| tracing::warn!("Can not toggle microphone mute: {}", e));
+                    }
+                },
+            );
+        })
+        .await;
+    }
+}
@@ -0,0 +1,25 @@
+use super::super::super::_audio::{
+    pipewire::{watch_default_node, AudioNode},
+    DeviceKind,
+};
+use super::super::super::_base::{ProviderContext, ProviderResult};
+use super::{is_toggle_mute, send_data};
+
+/// Tracks default source through PipeWire, used when there is no PulseAudio server
+pub fn run(ctx: ProviderContext) -> ProviderResult {
+    let data_sender = ctx.data_sender.clone();
+    let mut synced = None;
+    return watch_default_node(
+        ctx,
+        DeviceKind::Source,
+        move |source| send_data(source.map(AudioNode::get_volume).unwrap_or_default(), &mut synced, &data_sender),
+        |source, data| {
+            if is_toggle_mute(data) {
+                source
+                    .ok_or("no default source".to_string())
+                    .and_then(|x| x.set_mute(!x.get_volume().1))
+                    .unwrap_or_else(|e| tracing::warn!("Can not toggle microphone mute: {}", e));
+            }
+        },
+    );
+}
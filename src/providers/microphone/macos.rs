@@ -0,0 +1,136 @@
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::KEYBOARD_TOGGLE_MUTE;
+use crate::data_type::DataType;
+use coreaudio_sys::{
+    kAudioDevicePropertyMute, kAudioDevicePropertyVolumeScalar, kAudioHardwarePropertyDefaultInputDevice,
+    kAudioObjectPropertyElementMaster, kAudioObjectPropertyScopeGlobal, kAudioObjectPropertyScopeInput, kAudioObjectSystemObject,
+    AudioObjectGetPropertyData, AudioObjectPropertyAddress, AudioObjectSetPropertyData,
+};
+use libc::c_void;
+use tokio::sync::mpsc;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+const POLLS_PER_CHECK: u32 = 10;
+
+unsafe fn get_property<T: Default>(device_id: u32, selector: u32, scope: u32) -> Option<T> {
+    let address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let mut value = T::default();
+    let mut size = std::mem::size_of::<T>() as u32;
+    let status = AudioObjectGetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        &mut value as *mut T as *mut c_void,
+    );
+
+    if status == 0 {
+        Some(value)
+    } else {
+        tracing::debug!("Failed to get property {} of device {}. Status: {}", selector, device_id, status);
+        None
+    }
+}
+
+unsafe fn get_default_input_device() -> Option<u32> {
+    get_property::<u32>(
+        kAudioObjectSystemObject,
+        kAudioHardwarePropertyDefaultInputDevice,
+        kAudioObjectPropertyScopeGlobal,
+    )
+}
+
+/// Громкость и mute микрофона по умолчанию
+unsafe fn get_volume() -> Option<(f32, bool)> {
+    let device_id = get_default_input_device()?;
+    let volume = get_property::<f32>(device_id, kAudioDevicePropertyVolumeScalar, kAudioObjectPropertyScopeInput)?;
+    // Не у всех устройств есть свойство mute
+    let muted = get_property::<u32>(device_id, kAudioDevicePropertyMute, kAudioObjectPropertyScopeInput).unwrap_or_default();
+    Some((volume, muted != 0))
+}
+
+unsafe fn toggle_mute() -> Result<(), String> {
+    let device_id = get_default_input_device().ok_or("no default input device")?;
+    let muted =
+        get_property::<u32>(device_id, kAudioDevicePropertyMute, kAudioObjectPropertyScopeInput).ok_or("device does not support mute")?;
+    let address = AudioObjectPropertyAddress {
+        mSelector: kAudioDevicePropertyMute,
+        mScope: kAudioObjectPropertyScopeInput,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let value: u32 = (muted == 0) as u32;
+    let status = AudioObjectSetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        std::mem::size_of::<u32>() as u32,
+        &value as *const u32 as *const c_void,
+    );
+
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(format!("status {}", status))
+    }
+}
+
+fn send_data(value: (f32, bool), data_sender: &mpsc::Sender<Vec<u8>>) {
+    let (volume, muted) = value;
+    let data = vec![DataType::Microphone as u8, (volume * 100.0).round() as u8, muted as u8];
+    data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+}
+
+pub struct MicrophoneProvider {}
+
+impl MicrophoneProvider {
+    pub fn new() -> Box<dyn Provider> {
+        let provider = MicrophoneProvider {};
+        Box::new(provider)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for MicrophoneProvider {
+    fn name(&self) -> &str {
+        "Microphone"
+    }
+
+    async fn run(&self, mut ctx: ProviderContext) -> ProviderResult {
+        let mut synced = None;
+
+        run_blocking(move || {
+            let mut polls: u32 = 0;
+            while !ctx.cancellation_token.is_cancelled() {
+                let mut toggled = false;
+                while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+                    if data.first() == Some(&(DataType::Microphone as u8)) && data.get(1) == Some(&KEYBOARD_TOGGLE_MUTE) {
+                        unsafe { toggle_mute() }.unwrap_or_else(|e| tracing::warn!("Can not toggle microphone mute: {}", e));
+                        toggled = true;
+                    }
+                }
+
+                // Громкость проверяется реже, чем команды с клавиатуры
+                if toggled || polls % POLLS_PER_CHECK == 0 {
+                    if let Some(volume) = unsafe { get_volume() }.filter(|x| Some(*x) != synced) {
+                        synced = Some(volume);
+                        send_data(volume, &ctx.data_sender);
+                    }
+                }
+
+                polls = polls.wrapping_add(1);
+                std::thread::sleep(POLL_INTERVAL);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
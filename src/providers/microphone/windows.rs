@@ -0,0 +1,99 @@
+use tokio::sync::mpsc;
+use windows::{
+    core::Error,
+    Win32::{
+        Foundation::BOOL,
+        Media::Audio::{
+            eCapture, eCommunications,
+            Endpoints::{IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl},
+            IMMDeviceEnumerator, MMDeviceEnumerator, AUDIO_VOLUME_NOTIFICATION_DATA,
+        },
+        System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED},
+    },
+};
+
+use crate::data_type::DataType;
+
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::KEYBOARD_TOGGLE_MUTE;
+
+/// Default communications device is the one used by call apps
+unsafe fn get_volume_endpoint() -> Result<IAudioEndpointVolume, Error> {
+    let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+    let instance: windows::core::Result<IMMDeviceEnumerator> = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_INPROC_SERVER);
+    return instance?
+        .GetDefaultAudioEndpoint(eCapture, eCommunications)?
+        .Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None);
+}
+
+#[windows::core::implement(IAudioEndpointVolumeCallback)]
+struct VolumeChangeCallback {
+    push_sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl IAudioEndpointVolumeCallback_Impl for VolumeChangeCallback {
+    fn OnNotify(&self, notification_data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<(), windows::core::Error> {
+        let data = unsafe { *notification_data };
+        send_data(&data.fMasterVolume, data.bMuted.as_bool(), &self.push_sender);
+        return Ok(());
+    }
+}
+
+fn send_data(value: &f32, muted: bool, push_sender: &mpsc::Sender<Vec<u8>>) {
+    let volume = (value * 100.0).round() as u8;
+    let data = vec![DataType::Microphone as u8, volume, muted as u8];
+    push_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+}
+
+pub struct MicrophoneProvider {}
+
+impl MicrophoneProvider {
+    pub fn new() -> Box<dyn Provider> {
+        let provider = MicrophoneProvider {};
+        return Box::new(provider);
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for MicrophoneProvider {
+    fn name(&self) -> &str {
+        return "Microphone";
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        return run_blocking(move || subscribe_and_wait(ctx)).await;
+    }
+}
+
+fn subscribe_and_wait(mut ctx: ProviderContext) -> ProviderResult {
+    let endpoint_volume = unsafe { get_volume_endpoint() }.map_err(|e| format!("Can not get microphone endpoint: {}", e))?;
+    if let Ok(volume) = unsafe { endpoint_volume.GetMasterVolumeLevelScalar() } {
+        let muted = unsafe { endpoint_volume.GetMute() }.map(|x| x.as_bool()).unwrap_or_default();
+        send_data(&volume, muted, &ctx.data_sender);
+    }
+
+    let volume_callback: IAudioEndpointVolumeCallback = VolumeChangeCallback {
+        push_sender: ctx.data_sender.clone(),
+    }
+    .into();
+    unsafe { endpoint_volume.RegisterControlChangeNotify(&volume_callback) }
+        .map_err(|e| format!("Can not register Microphone callback: {}", e))?;
+
+    while !ctx.cancellation_token.is_cancelled() {
+        while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+            if data.first() == Some(&(DataType::Microphone as u8)) && data.get(1) == Some(&KEYBOARD_TOGGLE_MUTE) {
+                let result = unsafe {
+                    endpoint_volume
+                        .GetMute()
+                        .and_then(|x| endpoint_volume.SetMute(BOOL::from(!x.as_bool()), std::ptr::null()))
+                };
+                result.unwrap_or_else(|e| tracing::warn!("Can not toggle microphone mute: {}", e));
+            }
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    let _ = unsafe { endpoint_volume.UnregisterControlChangeNotify(&volume_callback) };
+    return Ok(());
+}
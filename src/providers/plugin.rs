@@ -0,0 +1,340 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, mpsc},
+};
+use tokio_util::sync::CancellationToken;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store};
+
+use crate::{config::PluginsConfig, data_type::DataType, keyboard::REPORT_LENGTH};
+
+use super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+
+/// Module name of all host functions
+const HOST_MODULE: &str = "qmk";
+/// Roughly number of instructions plugin can execute between `wait` calls, limits plugins that never yield
+const FUEL_PER_WAIT: u64 = 10_000_000;
+
+const ERROR_MEMORY: i32 = -1;
+const ERROR_QUEUE_FULL: i32 = -2;
+const ERROR_FILE_READ: i32 = -3;
+const ERROR_FILE_NOT_ALLOWED: i32 = -4;
+const ERROR_INVALID_MESSAGE: i32 = -5;
+const WAIT_CANCELLED: i32 = -1;
+
+struct PluginState {
+    name: String,
+    data_sender: mpsc::Sender<Vec<u8>>,
+    keyboard_receiver: broadcast::Receiver<Vec<u8>>,
+    cancellation_token: CancellationToken,
+    runtime: Handle,
+    allowed_files: Vec<PathBuf>,
+    /// Last keyboard message returned by `wait`, copied to plugin memory by `read_message`
+    message: Vec<u8>,
+}
+
+/// Range is checked against plugin memory before copying, so plugin can not make host allocate more than it has
+fn read_memory(caller: &Caller<'_, PluginState>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    return memory.data(caller).get(start..end).map(|x| x.to_vec());
+}
+
+fn write_memory(caller: &mut Caller<'_, PluginState>, ptr: i32, data: &[u8]) -> Option<()> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    return memory.write(caller, usize::try_from(ptr).ok()?, data).ok();
+}
+
+/// Allowed files are resolved when plugin starts, so entries that do not exist yet are skipped until the next start
+fn get_allowed_files(name: &str, allowed_files: &[String]) -> Vec<PathBuf> {
+    return allowed_files
+        .iter()
+        .filter_map(|x| {
+            Path::new(x)
+                .canonicalize()
+                .map_err(|e| tracing::warn!("{} Plugin allowed file {} is skipped until plugin restart: {}", name, x, e))
+                .ok()
+        })
+        .collect();
+}
+
+/// Returns canonical path if it is inside one of allowed files, it must be used for reading instead of the original path
+fn get_allowed_path(path: &Path, allowed_files: &[PathBuf]) -> Option<PathBuf> {
+    return path
+        .canonicalize()
+        .ok()
+        .filter(|path| allowed_files.iter().any(|x| path.starts_with(x)));
+}
+
+/// Message must fit into a report and must not use built-in type, like messages of command providers
+fn is_message_valid(data: &[u8]) -> bool {
+    return data.len() <= REPORT_LENGTH && data.first().is_some_and(|x| DataType::from_u8(*x).is_none());
+}
+
+/// Host ABI, all functions are imported from `qmk` module and return negative values on errors:
+/// - `emit(ptr, len) -> i32` - sends message to keyboard, first byte is message type which must not be built-in
+/// - `wait(timeout_ms) -> i32` - sleeps until timeout (returns `0`) or keyboard message (returns its length), `-1` means plugin must return from `run`
+/// - `read_message(ptr, len) -> i32` - copies last keyboard message returned by `wait`, returns copied length
+/// - `read_file(path_ptr, path_len, ptr, len) -> i32` - reads allowed file, returns full file length even if only `len` bytes are copied
+/// - `log(ptr, len)` - writes UTF-8 string to app log
+fn create_linker(engine: &Engine) -> Result<Linker<PluginState>, wasmi::Error> {
+    let mut linker = Linker::<PluginState>::new(engine);
+
+    linker.func_wrap(HOST_MODULE, "emit", |caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+        if len > REPORT_LENGTH as i32 {
+            return ERROR_INVALID_MESSAGE;
+        }
+
+        let Some(data) = read_memory(&caller, ptr, len) else {
+            return ERROR_MEMORY;
+        };
+
+        if !is_message_valid(&data) {
+            return ERROR_INVALID_MESSAGE;
+        }
+
+        return match caller.data().data_sender.try_send(data) {
+            Ok(_) => 0,
+            Err(_) => ERROR_QUEUE_FULL,
+        };
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "wait", |mut caller: Caller<'_, PluginState>, timeout_ms: i32| -> i32 {
+        let _ = caller.set_fuel(FUEL_PER_WAIT);
+        let timeout = std::time::Duration::from_millis(timeout_ms.max(0) as u64);
+        let PluginState {
+            keyboard_receiver,
+            cancellation_token,
+            runtime,
+            message,
+            ..
+        } = caller.data_mut();
+
+        return runtime.block_on(async {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => WAIT_CANCELLED,
+                _ = tokio::time::sleep(timeout) => 0,
+                received = keyboard_receiver.recv() => match received {
+                    Ok(received) => {
+                        *message = received;
+                        message.len() as i32
+                    }
+                    Err(_) => 0,
+                },
+            }
+        });
+    })?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_message",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let mut message = caller.data().message.clone();
+            message.truncate(len.max(0) as usize);
+            return match write_memory(&mut caller, ptr, &message) {
+                Some(_) => message.len() as i32,
+                None => ERROR_MEMORY,
+            };
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_file",
+        |mut caller: Caller<'_, PluginState>, path_ptr: i32, path_len: i32, ptr: i32, len: i32| -> i32 {
+            let Some(path) = read_memory(&caller, path_ptr, path_len) else {
+                return ERROR_MEMORY;
+            };
+
+            let path = PathBuf::from(String::from_utf8_lossy(&path).to_string());
+            let Some(path) = get_allowed_path(&path, &caller.data().allowed_files) else {
+                tracing::warn!("{} Plugin is not allowed to read {}", caller.data().name, path.display());
+                return ERROR_FILE_NOT_ALLOWED;
+            };
+
+            let Ok(mut content) = std::fs::read(&path) else {
+                return ERROR_FILE_READ;
+            };
+
+            let file_length = content.len() as i32;
+            content.truncate(len.max(0) as usize);
+            return match write_memory(&mut caller, ptr, &content) {
+                Some(_) => file_length,
+                None => ERROR_MEMORY,
+            };
+        },
+    )?;
+
+    linker.func_wrap(HOST_MODULE, "log", |caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+        if let Some(data) = read_memory(&caller, ptr, len) {
+            tracing::info!("{} Plugin: {}", caller.data().name, String::from_utf8_lossy(&data));
+        }
+    })?;
+
+    return Ok(linker);
+}
+
+/// Runs WebAssembly module exporting `run` function in a sandbox, it can access only host functions from `qmk` module
+pub struct PluginProvider {
+    name: String,
+    module: Arc<Module>,
+    allowed_files: Vec<String>,
+}
+
+impl PluginProvider {
+    pub fn new(name: String, module: Module, allowed_files: Vec<String>) -> Box<dyn Provider> {
+        let provider = PluginProvider {
+            name,
+            module: Arc::new(module),
+            allowed_files,
+        };
+        return Box::new(provider);
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for PluginProvider {
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let state = PluginState {
+            name: self.name.clone(),
+            data_sender: ctx.data_sender,
+            keyboard_receiver: ctx.keyboard_receiver,
+            cancellation_token: ctx.cancellation_token,
+            runtime: Handle::current(),
+            allowed_files: get_allowed_files(&self.name, &self.allowed_files),
+            message: vec![],
+        };
+        let module = self.module.clone();
+
+        return run_blocking(move || {
+            let engine = module.engine().clone();
+            let linker = create_linker(&engine).map_err(|e| e.to_string())?;
+            let mut store = Store::new(&engine, state);
+            store.set_fuel(FUEL_PER_WAIT).map_err(|e| e.to_string())?;
+
+            let instance = linker
+                .instantiate(&mut store, &module)
+                .and_then(|x| x.start(&mut store))
+                .map_err(|e| format!("Can not instantiate plugin: {}", e))?;
+            let run = instance
+                .get_typed_func::<(), ()>(&store, "run")
+                .map_err(|e| format!("Plugin does not export run function: {}", e))?;
+
+            return run.call(&mut store, ()).map_err(|e| format!("Plugin trapped: {}", e));
+        })
+        .await;
+    }
+}
+
+/// Fuel is consumed by every instruction, so plugin that never calls `wait` traps
+fn create_engine() -> Engine {
+    let mut engine_config = wasmi::Config::default();
+    engine_config.consume_fuel(true);
+    return Engine::new(&engine_config);
+}
+
+/// Compiles all plugins from configured directory, invalid plugins are logged and skipped
+pub fn load_plugins(config: &PluginsConfig) -> Vec<Box<dyn Provider>> {
+    let Ok(entries) = std::fs::read_dir(&config.directory) else {
+        tracing::debug!("Plugins directory {} not found", config.directory);
+        return vec![];
+    };
+
+    let engine = create_engine();
+
+    let mut paths = entries
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| x.extension().is_some_and(|x| x == "wasm"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    return paths
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().to_string();
+            let module = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|x| Module::new(&engine, &x).map_err(|e| e.to_string()))
+                .map_err(|e| tracing::error!("Can not load plugin {}: {}", path.display(), e))
+                .ok()?;
+
+            tracing::info!("Loaded plugin {}", name);
+            let allowed_files = config.allowed_files.get(&name).cloned().unwrap_or_default();
+            Some(PluginProvider::new(name, module, allowed_files))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str) -> Box<dyn Provider> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/plugins").join(name);
+        let wasm = wat::parse_file(path).unwrap();
+        let module = Module::new(&create_engine(), &wasm[..]).unwrap();
+        return PluginProvider::new(name.to_string(), module, vec![]);
+    }
+
+    /// Runs plugin until `run` returns, returns its result and all emitted messages
+    async fn run(provider: Box<dyn Provider>) -> (ProviderResult, Vec<Vec<u8>>) {
+        let (data_sender, mut data_receiver) = mpsc::channel(10);
+        let (_keyboard_sender, keyboard_receiver) = broadcast::channel(10);
+        let ctx = ProviderContext {
+            data_sender,
+            keyboard_receiver,
+            resume_sender: broadcast::channel(1).0,
+            cancellation_token: CancellationToken::new(),
+        };
+        let result = provider.run(ctx).await;
+
+        let mut messages = vec![];
+        while let Ok(message) = data_receiver.try_recv() {
+            messages.push(message);
+        }
+
+        return (result, messages);
+    }
+
+    #[tokio::test]
+    async fn emit_checks_memory_and_message() {
+        let (result, messages) = run(load("emit.wat")).await;
+        assert_eq!(result, Ok(()));
+        let errors = [
+            ERROR_MEMORY,
+            ERROR_MEMORY,
+            ERROR_MEMORY,
+            ERROR_INVALID_MESSAGE,
+            ERROR_INVALID_MESSAGE,
+            ERROR_INVALID_MESSAGE,
+        ];
+        let mut results = vec![0x80];
+        results.extend(errors.map(|x| x as u8));
+        assert_eq!(messages, vec![vec![0x80, 1, 2], results]);
+    }
+
+    #[tokio::test]
+    async fn plugin_without_wait_runs_out_of_fuel() {
+        let (result, messages) = run(load("loop.wat")).await;
+        assert!(result.is_err_and(|e| e.starts_with("Plugin trapped")));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn built_in_and_long_messages_are_invalid() {
+        assert!(is_message_valid(&[0x80, 1]));
+        assert!(is_message_valid(&[0x80; REPORT_LENGTH]));
+        assert!(!is_message_valid(&[0x80; REPORT_LENGTH + 1]));
+        assert!(!is_message_valid(&[DataType::Volume as u8, 50, 0]));
+        assert!(!is_message_valid(&[]));
+    }
+}
@@ -1,60 +1,274 @@
-use chrono::{DateTime, Local, Timelike};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use chrono_tz::Tz;
 use tokio::sync::{broadcast, mpsc};
 
-use crate::data_type::DataType;
+use crate::{
+    config::{HourFormat, TimeProviderConfig},
+    data_type::DataType,
+    timer::{Timer, TimerStatus},
+};
+
+use super::_base::{Provider, ProviderContext, ProviderResult};
 
-use super::_base::Provider;
+#[cfg(target_os = "linux")]
+mod sleep;
 
-fn get_time() -> (u8, u8) {
-    let now: DateTime<Local> = Local::now();
-    let hour = now.hour() as u8;
+const HOUR_FORMAT_24H: u8 = 0;
+const HOUR_FORMAT_AM: u8 = 1;
+const HOUR_FORMAT_PM: u8 = 2;
+
+/// Returns `(hour, minute, format)`, where format is `0` for 24h, `1` for AM and `2` for PM
+fn get_time<T: TimeZone>(now: &DateTime<T>, hour_format: HourFormat) -> (u8, u8, u8) {
     let minute = now.minute() as u8;
-    return (hour, minute);
+    return match hour_format {
+        HourFormat::H24 => (now.hour() as u8, minute, HOUR_FORMAT_24H),
+        HourFormat::H12 => {
+            let (is_pm, hour) = now.hour12();
+            (hour as u8, minute, if is_pm { HOUR_FORMAT_PM } else { HOUR_FORMAT_AM })
+        }
+    };
+}
+
+/// Returns `(day, month, years since 2000)`
+fn get_date(now: &DateTime<Local>) -> (u8, u8, u8) {
+    return (
+        now.day() as u8,
+        now.month() as u8,
+        (now.year() - 2000).clamp(0, u8::MAX as i32) as u8,
+    );
+}
+
+/// Time left until the next second or minute starts
+fn get_delay_to_boundary(now: &DateTime<Local>, seconds: bool) -> Duration {
+    let nanos = now.nanosecond().min(999_999_999);
+    let to_next_second = Duration::from_nanos((1_000_000_000 - nanos) as u64);
+    if seconds {
+        return to_next_second;
+    }
+
+    return to_next_second + Duration::from_secs((59 - now.second().min(59)) as u64);
+}
+
+/// Returns `[type, ...timestamp in milliseconds as u64 LE, ...UTC offset in minutes as i16 LE]`
+fn get_clock_sync(now: &DateTime<Local>) -> Vec<u8> {
+    let offset = (now.offset().local_minus_utc() / 60) as i16;
+    let mut data = vec![DataType::ClockSync as u8];
+    data.extend_from_slice(&(now.timestamp_millis() as u64).to_le_bytes());
+    data.extend_from_slice(&offset.to_le_bytes());
+    return data;
+}
+
+/// Label is limited so the message fits into a single report
+const MAX_LABEL_LENGTH: usize = 16;
+
+/// Returns `[type, index, hour, minute, format, label length, ...label]`
+fn get_zone_time(index: usize, time: (u8, u8, u8), label: &str) -> Vec<u8> {
+    let mut label = label.as_bytes().to_vec();
+    label.truncate(MAX_LABEL_LENGTH);
+    let mut data = vec![DataType::ZoneTime as u8, index as u8, time.0, time.1, time.2, label.len() as u8];
+    data.extend(label);
+    return data;
 }
 
-fn send_data(value: &(u8, u8), push_sender: &mpsc::Sender<Vec<u8>>) {
-    let data = vec![DataType::Time as u8, value.0, value.1];
+/// Difference between wall clock and monotonic clock progress treated as suspend or clock change
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+/// Suspend is usually detected twice - by logind signal and by clock jump
+const RESYNC_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Detects wall clock jumps by comparing its progress with monotonic clock, which is never adjusted and does not count suspend time
+struct ClockWatch {
+    instant: Instant,
+    wall: DateTime<Local>,
+}
+
+impl ClockWatch {
+    fn new() -> Self {
+        return Self {
+            instant: Instant::now(),
+            wall: Local::now(),
+        };
+    }
+
+    /// Returns `true` if wall clock moved differently from monotonic clock since the last check
+    fn check(&mut self, now: &DateTime<Local>) -> bool {
+        let wall_elapsed = (*now - self.wall).num_milliseconds();
+        let monotonic_elapsed = self.instant.elapsed().as_millis() as i64;
+        self.instant = Instant::now();
+        self.wall = *now;
+        return (wall_elapsed - monotonic_elapsed).unsigned_abs() > CLOCK_JUMP_THRESHOLD.as_millis() as u64;
+    }
+}
+
+/// Returns `[type, phase, remaining seconds as u16 LE, paused]`
+fn get_timer(status: TimerStatus) -> Vec<u8> {
+    let mut data = vec![DataType::Timer as u8, status.phase as u8];
+    data.extend_from_slice(&(status.remaining_seconds.min(u16::MAX as u32) as u16).to_le_bytes());
+    data.push(status.paused as u8);
+    return data;
+}
+
+fn send_data(data: Vec<u8>, push_sender: &mpsc::Sender<Vec<u8>>) {
     push_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
 }
 
+#[derive(Default)]
+struct SyncedState {
+    time: Option<(u8, u8, u8)>,
+    seconds: Option<u8>,
+    date: Option<(u8, u8, u8)>,
+    weekday: Option<u8>,
+    /// UTC offset in seconds and time of the last clock sync
+    clock_sync: Option<(i32, Instant)>,
+    zones: Vec<Option<(u8, u8, u8)>>,
+    timer: Option<TimerStatus>,
+    /// Kept between resyncs
+    resynced_at: Option<Instant>,
+}
+
 pub struct TimeProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: TimeProviderConfig,
+    zones: Vec<(Tz, String)>,
+    timer: Timer,
 }
 
 impl TimeProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = TimeProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: TimeProviderConfig, timer: Timer) -> Box<dyn Provider> {
+        let zones = config
+            .zones
+            .iter()
+            .filter_map(|x| x.zone.parse::<Tz>().ok().map(|tz| (tz, x.label.clone())))
+            .collect();
+        let provider = TimeProvider { config, zones, timer };
         return Box::new(provider);
     }
+
+    /// Forgets synced values so everything is sent again and notifies other providers
+    fn resync(&self, reason: &str, synced: &mut SyncedState, resume_sender: &broadcast::Sender<()>) {
+        if synced.resynced_at.is_some_and(|x| x.elapsed() < RESYNC_DEBOUNCE) {
+            return;
+        }
+
+        tracing::info!("{} Provider detected {}, resyncing", self.name(), reason);
+        *synced = SyncedState {
+            resynced_at: Some(Instant::now()),
+            ..Default::default()
+        };
+        let _ = resume_sender.send(());
+    }
+
+    /// Sends every enabled value that changed since the last sync
+    fn sync(&self, now: &DateTime<Local>, synced: &mut SyncedState, push_sender: &mpsc::Sender<Vec<u8>>) {
+        let time = get_time(now, self.config.hour_format);
+        if synced.time != Some(time) {
+            synced.time = Some(time);
+            send_data(vec![DataType::Time as u8, time.0, time.1, time.2], push_sender);
+        }
+
+        let seconds = now.second().min(59) as u8;
+        if self.config.seconds && synced.seconds != Some(seconds) {
+            synced.seconds = Some(seconds);
+            send_data(vec![DataType::TimeSeconds as u8, seconds], push_sender);
+        }
+
+        let date = get_date(now);
+        if self.config.date && synced.date != Some(date) {
+            synced.date = Some(date);
+            send_data(vec![DataType::Date as u8, date.0, date.1, date.2], push_sender);
+        }
+
+        let weekday = now.weekday().number_from_monday() as u8;
+        if self.config.weekday && synced.weekday != Some(weekday) {
+            synced.weekday = Some(weekday);
+            send_data(vec![DataType::Weekday as u8, weekday], push_sender);
+        }
+
+        let offset = now.offset().local_minus_utc();
+        let clock_sync_interval = Duration::from_millis(self.config.clock_sync_interval);
+        let clock_sync_due = synced
+            .clock_sync
+            .is_none_or(|(synced_offset, synced_at)| synced_offset != offset || synced_at.elapsed() >= clock_sync_interval);
+        if self.config.clock_sync && clock_sync_due {
+            synced.clock_sync = Some((offset, Instant::now()));
+            send_data(get_clock_sync(&Local::now()), push_sender);
+        }
+
+        synced.zones.resize(self.zones.len(), None);
+        for (index, (tz, label)) in self.zones.iter().enumerate() {
+            let time = get_time(&now.with_timezone(tz), self.config.hour_format);
+            if synced.zones[index] != Some(time) {
+                synced.zones[index] = Some(time);
+                send_data(get_zone_time(index, time, label), push_sender);
+            }
+        }
+
+        let timer = self.timer.status();
+        if synced.timer != Some(timer) {
+            synced.timer = Some(timer);
+            send_data(get_timer(timer), push_sender);
+        }
+    }
 }
 
+#[async_trait::async_trait]
 impl Provider for TimeProvider {
-    fn start(&self) {
-        tracing::info!("Time Provider enabled");
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
-            let mut synced_time = (0u8, 0u8);
-            loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    break;
-                }
+    fn name(&self) -> &str {
+        return "Time";
+    }
 
-                let time = get_time();
-                if synced_time != time {
-                    synced_time = time;
-                    send_data(&synced_time, &data_sender);
-                }
+    async fn run(&self, mut ctx: ProviderContext) -> ProviderResult {
+        let (resume_sender, mut resume_receiver) = mpsc::channel::<()>(1);
+        #[cfg(target_os = "linux")]
+        {
+            let cancellation_token = ctx.cancellation_token.clone();
+            tokio::task::spawn_blocking(move || {
+                sleep::watch_resume(resume_sender, cancellation_token)
+                    .unwrap_or_else(|e| tracing::warn!("Time Provider can not watch system sleep: {}", e));
+            });
+        }
+        #[cfg(not(target_os = "linux"))]
+        drop(resume_sender);
+
+        let mut timer_changed = self.timer.subscribe_changes();
+        let mut timer_finished = self.timer.subscribe_finished();
+        let mut synced = SyncedState::default();
+        let mut clock = ClockWatch::new();
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        loop {
+            let now = Local::now();
+            if clock.check(&now) {
+                self.resync("clock change", &mut synced, &ctx.resume_sender);
+            }
+
+            self.sync(&now, &mut synced, &ctx.data_sender);
 
-                std::thread::sleep(std::time::Duration::from_secs(1));
+            let mut delay = get_delay_to_boundary(&now, self.config.seconds).min(poll_interval);
+            if let Some((_, synced_at)) = synced.clock_sync {
+                let clock_sync_interval = Duration::from_millis(self.config.clock_sync_interval);
+                delay = delay.min(clock_sync_interval.saturating_sub(synced_at.elapsed()));
+            }
+
+            if let Some(tick) = self.timer.get_next_tick() {
+                delay = delay.min(tick);
+            }
+
+            tokio::select! {
+                _ = ctx.cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(delay) => (),
+                Some(_) = resume_receiver.recv() => self.resync("system resume", &mut synced, &ctx.resume_sender),
+                _ = timer_changed.changed() => (),
+                Ok((finished, next)) = timer_finished.recv() => {
+                    send_data(vec![DataType::TimerAlert as u8, finished as u8, next as u8], &ctx.data_sender);
+                }
+                Ok(data) = ctx.keyboard_receiver.recv() => {
+                    if data.first() == Some(&(DataType::Timer as u8)) {
+                        self.timer.handle_keyboard_command(&data);
+                    }
+                }
             }
+        }
 
-            tracing::info!("Time Provider stopped");
-        });
+        return Ok(());
     }
 }
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use dbus::{blocking::Connection, message::MatchRule};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::super::_base::ProviderResult;
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+/// How often cancellation is checked while waiting for D-Bus messages
+const PROCESS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Listens to logind `PrepareForSleep` signal and notifies `resume_sender` when system wakes up, blocks until cancelled
+pub fn watch_resume(resume_sender: mpsc::Sender<()>, cancellation_token: CancellationToken) -> ProviderResult {
+    let connection = Connection::new_system().map_err(|e| format!("Can not connect to system D-Bus: {}", e))?;
+    let rule = MatchRule::new_signal(LOGIND_MANAGER, "PrepareForSleep").with_sender(LOGIND_SERVICE);
+    connection
+        .add_match(rule, move |(sleeping,): (bool,), _, _| {
+            if !sleeping {
+                let _ = resume_sender.try_send(());
+            }
+
+            return true;
+        })
+        .map_err(|e| format!("Can not subscribe to logind signals: {}", e))?;
+
+    while !cancellation_token.is_cancelled() {
+        connection.process(PROCESS_TIMEOUT).map_err(|e| e.to_string())?;
+    }
+
+    return Ok(());
+}
@@ -1,3 +1,61 @@
+use tokio::sync::mpsc;
+
+use crate::data_type::DataType;
+
+/// Command sent by keyboard as `[type, command, value]`
+enum KeyboardCommand {
+    ToggleMute,
+    /// Volume in percents
+    SetVolume(u8),
+    SetMute(bool),
+}
+
+impl KeyboardCommand {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.first() != Some(&(DataType::Volume as u8)) {
+            return None;
+        }
+
+        let value = data.get(2).copied().unwrap_or_default();
+        return match data.get(1).copied() {
+            Some(0) => Some(Self::ToggleMute),
+            Some(1) => Some(Self::SetVolume(value.min(100))),
+            Some(2) => Some(Self::SetMute(value != 0)),
+            x => {
+                tracing::warn!("Unknown volume command from keyboard: {:?}", x);
+                None
+            }
+        };
+    }
+}
+
+/// Kind of output device, lets firmware show an icon
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+enum FormFactor {
+    #[default]
+    Unknown = 0,
+    Speakers = 1,
+    Headphones = 2,
+    Hdmi = 3,
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+struct OutputDevice {
+    name: String,
+    form_factor: FormFactor,
+}
+
+impl OutputDevice {
+    fn send(&self, max_length: usize, data_sender: &mpsc::Sender<Vec<u8>>) {
+        tracing::info!("Default output device: {} ({:?})", self.name, self.form_factor);
+        let mut name = self.name.as_bytes().to_vec();
+        name.truncate(max_length);
+        let mut data = vec![DataType::OutputDevice as u8, self.form_factor as u8, name.len() as u8];
+        data.extend(name);
+        data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod linux;
 
@@ -14,4 +72,4 @@ pub use self::windows::VolumeProvider;
 mod macos;
 
 #[cfg(target_os = "macos")]
-pub use self::macos::VolumeProvider;
\ No newline at end of file
+pub use self::macos::VolumeProvider;
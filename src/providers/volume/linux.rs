@@ -1,79 +1,139 @@
-use std::ops::Deref;
+use libpulse_binding::{context::introspect::Introspector, proplist::properties, volume::Volume};
+use pulsectl::controllers::types::DeviceInfo;
+use tokio::sync::mpsc;
+
+use crate::{config::VolumeProviderConfig, data_type::DataType};
+
+use super::super::_audio::{pulse, DeviceKind};
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::{FormFactor, KeyboardCommand, OutputDevice};
+
+#[cfg(feature = "pipewire")]
+mod pipewire;
+
+/// Form factor is taken from device properties (set for USB and Bluetooth devices) or guessed from port and device names
+fn get_form_factor(form_factor: Option<&str>, names: &[&str]) -> FormFactor {
+    let contains = |value: &str| names.iter().any(|x| x.contains(value));
+    return match form_factor {
+        Some("headset" | "headphone" | "hands-free" | "handset") => FormFactor::Headphones,
+        Some("tv") => FormFactor::Hdmi,
+        Some("speaker" | "internal" | "hifi" | "computer" | "portable" | "car") => FormFactor::Speakers,
+        _ if contains("hdmi") => FormFactor::Hdmi,
+        _ if contains("headphones") || contains("headset") => FormFactor::Headphones,
+        _ if contains("speaker") || contains("lineout") => FormFactor::Speakers,
+        _ => FormFactor::Unknown,
+    };
+}
 
-use libpulse_binding::context::subscribe::Facility;
-use pulsectl::controllers::{DeviceControl, SinkController};
-use tokio::sync::{broadcast, mpsc};
+fn get_output_device(sink: &DeviceInfo) -> OutputDevice {
+    let form_factor = sink.proplist.get_str(properties::DEVICE_FORM_FACTOR);
+    let port = sink.active_port.as_ref().and_then(|x| x.name.clone()).unwrap_or_default();
+    let name = sink.name.clone().unwrap_or_default();
+    return OutputDevice {
+        form_factor: get_form_factor(form_factor.as_deref(), &[&port, &name]),
+        name: sink.description.clone().unwrap_or(name),
+    };
+}
 
-use crate::data_type::DataType;
+/// State sent to keyboard
+#[derive(Default)]
+struct State {
+    device: Option<OutputDevice>,
+    volume: Option<(f32, bool)>,
+}
 
-use super::super::_base::Provider;
+impl State {
+    fn update(&mut self, device: OutputDevice, volume: (f32, bool), max_length: usize, data_sender: &mpsc::Sender<Vec<u8>>) {
+        if self.device.as_ref() != Some(&device) {
+            device.send(max_length, data_sender);
+            self.device = Some(device);
+        }
+
+        if self.volume != Some(volume) {
+            send_data(&volume, data_sender);
+            self.volume = Some(volume);
+        }
+    }
+}
 
-fn get_volume() -> Option<f32> {
-    let mut controller = SinkController::create().ok()?;
-    if let Ok(default) = controller.get_default_device() {
-        let device_volume = default.volume.get().first()?.0 as f32;
-        let base_volume = default.base_volume.0 as f32;
-        return Some(device_volume / base_volume);
+/// Changes default sink, new state is sent after subscribe event
+fn handle_keyboard_command(introspector: &mut Introspector, sink: Option<&DeviceInfo>, command: KeyboardCommand) -> Result<(), String> {
+    let sink = sink.ok_or("no default sink")?;
+    match command {
+        KeyboardCommand::ToggleMute => {
+            introspector.set_sink_mute_by_index(sink.index, !sink.mute, None);
+        }
+        KeyboardCommand::SetMute(mute) => {
+            introspector.set_sink_mute_by_index(sink.index, mute, None);
+        }
+        KeyboardCommand::SetVolume(percent) => {
+            let mut volume = sink.volume;
+            let value = Volume((sink.base_volume.0 as f32 * percent as f32 / 100.0).round() as u32);
+            volume.scale(value).ok_or("invalid volume")?;
+            introspector.set_sink_volume_by_index(sink.index, &volume, None);
+        }
     }
 
-    return None;
+    return Ok(());
 }
 
-fn send_data(value: &f32, push_sender: &mpsc::Sender<Vec<u8>>) {
-    let volume = (value * 100.0).round() as u8;
-    let data = vec![DataType::Volume as u8, volume];
+fn send_data(value: &(f32, bool), push_sender: &mpsc::Sender<Vec<u8>>) {
+    let (volume, muted) = *value;
+    let data = vec![DataType::Volume as u8, (volume * 100.0).round() as u8, muted as u8];
     push_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
 }
 
 pub struct VolumeProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: VolumeProviderConfig,
 }
 
 impl VolumeProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = VolumeProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: VolumeProviderConfig) -> Box<dyn Provider> {
+        let provider = VolumeProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for VolumeProvider {
-    fn start(&self) {
-        tracing::info!("Volume Provider started");
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-
-        let mut volume = get_volume().unwrap_or_default();
-        send_data(&volume, &self.data_sender);
-
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
-
-            let controller = SinkController::create().map_err(|e| tracing::error!("{}", e)).unwrap();
-            let mut ctx = controller.handler.context.deref().borrow_mut();
-
-            ctx.set_subscribe_callback(Some(Box::new(move |_, _, _| {
-                let new_volume = get_volume().unwrap_or_default();
-                if volume != new_volume {
-                    volume = new_volume;
-                    send_data(&volume, &data_sender);
-                }
-            })));
-
-            ctx.subscribe(Facility::Sink.to_interest_mask(), |_| {});
+    fn name(&self) -> &str {
+        return "Volume";
+    }
 
-            loop {
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    break;
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let max_length = self.config.max_length;
+        return run_blocking(move || {
+            // PipeWire without pipewire-pulse has no PulseAudio server to connect to
+            let handler = match pulse::connect("VolumeProvider") {
+                Ok(handler) => handler,
+                #[cfg(feature = "pipewire")]
+                Err(e) => {
+                    tracing::info!("{}, using PipeWire", e);
+                    return pipewire::run(ctx, max_length);
                 }
-
-                controller.handler.mainloop.deref().borrow_mut().iterate(true);
-            }
-
-            tracing::info!("Volume Provider stopped");
-        });
+                #[cfg(not(feature = "pipewire"))]
+                Err(e) => return Err(e),
+            };
+
+            let data_sender = ctx.data_sender.clone();
+            let mut state = State::default();
+            return pulse::watch_default_device(
+                handler,
+                DeviceKind::Sink,
+                ctx,
+                move |sink| {
+                    let device = sink.map(get_output_device).unwrap_or_default();
+                    let volume = sink.and_then(pulse::get_volume).unwrap_or_default();
+                    state.update(device, volume, max_length, &data_sender);
+                },
+                |introspector, sink, data| {
+                    if let Some(command) = KeyboardCommand::parse(data) {
+                        handle_keyboard_command(introspector, sink, command)
+                            .unwrap_or_else(|e| tracing::warn!("Can not change volume: {}", e));
+                    }
+                },
+            );
+        })
+        .await;
     }
 }
@@ -0,0 +1,44 @@
+use super::super::super::_audio::{
+    pipewire::{watch_default_node, AudioNode},
+    DeviceKind,
+};
+use super::super::super::_base::{ProviderContext, ProviderResult};
+use super::super::{KeyboardCommand, OutputDevice};
+use super::{get_form_factor, State};
+
+fn get_output_device(sink: &AudioNode) -> OutputDevice {
+    return OutputDevice {
+        name: sink.description.clone().unwrap_or(sink.name.clone()),
+        form_factor: get_form_factor(sink.form_factor.as_deref(), &[&sink.name]),
+    };
+}
+
+/// Changes default sink, new state is received by param listener
+fn handle_keyboard_command(sink: Option<&AudioNode>, command: KeyboardCommand) -> Result<(), String> {
+    let sink = sink.ok_or("no default sink")?;
+    return match command {
+        KeyboardCommand::ToggleMute => sink.set_mute(!sink.get_volume().1),
+        KeyboardCommand::SetMute(mute) => sink.set_mute(mute),
+        KeyboardCommand::SetVolume(percent) => sink.set_volume(percent as f32 / 100.0),
+    };
+}
+
+/// Tracks default sink through PipeWire, used when there is no PulseAudio server
+pub fn run(ctx: ProviderContext, max_length: usize) -> ProviderResult {
+    let data_sender = ctx.data_sender.clone();
+    let mut state = State::default();
+    return watch_default_node(
+        ctx,
+        DeviceKind::Sink,
+        move |sink| {
+            let device = sink.map(get_output_device).unwrap_or_default();
+            let volume = sink.map(|x| x.get_volume()).unwrap_or_default();
+            state.update(device, volume, max_length, &data_sender);
+        },
+        |sink, data| {
+            if let Some(command) = KeyboardCommand::parse(data) {
+                handle_keyboard_command(sink, command).unwrap_or_else(|e| tracing::warn!("Can not change volume: {}", e));
+            }
+        },
+    );
+}
@@ -1,16 +1,26 @@
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::{FormFactor, KeyboardCommand, OutputDevice};
+use crate::{config::VolumeProviderConfig, data_type::DataType};
+use core_foundation::{
+    base::TCFType,
+    string::{CFString, CFStringRef},
+};
 use coreaudio_sys::{
-    AudioObjectGetPropertyData, AudioObjectPropertyAddress, kAudioObjectSystemObject,
-    kAudioHardwarePropertyDefaultOutputDevice, kAudioDevicePropertyVolumeScalar,
-    kAudioObjectPropertyScopeGlobal, kAudioObjectPropertyScopeOutput, kAudioObjectPropertyElementMaster,
+    kAudioDevicePropertyDataSource, kAudioDevicePropertyMute, kAudioDevicePropertyTransportType, kAudioDevicePropertyVolumeScalar,
+    kAudioDeviceTransportTypeBluetooth, kAudioDeviceTransportTypeBluetoothLE, kAudioDeviceTransportTypeBuiltIn,
+    kAudioDeviceTransportTypeDisplayPort, kAudioDeviceTransportTypeHDMI, kAudioHardwarePropertyDefaultOutputDevice,
+    kAudioObjectPropertyElementMaster, kAudioObjectPropertyName, kAudioObjectPropertyScopeGlobal, kAudioObjectPropertyScopeOutput,
+    kAudioObjectSystemObject, AudioObjectGetPropertyData, AudioObjectPropertyAddress, AudioObjectSetPropertyData,
 };
 use libc::c_void;
-use tokio::sync::{broadcast, mpsc};
-use std::sync::{Arc, Mutex};
-use crate::data_type::DataType;
-use super::super::_base::Provider;
+use tokio::sync::mpsc;
 
 const MIN_VOLUME_CHANGE: f32 = 0.05;
 const MIN_VOLUME_SEND_THRESHOLD: u8 = 1;
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+const POLLS_PER_CHECK: u32 = 10;
+/// Источник данных встроенного выхода, когда подключены наушники ('hdpn')
+const DATA_SOURCE_HEADPHONES: u32 = 0x6864706E;
 
 unsafe fn get_default_output_device() -> Option<u32> {
     let mut device_id: u32 = 0;
@@ -67,13 +77,143 @@ unsafe fn get_device_volume(device_id: u32) -> Option<f32> {
     }
 }
 
-fn send_data(volume: f32, data_sender: &mpsc::Sender<Vec<u8>>) {
+unsafe fn get_device_mute(device_id: u32) -> Option<bool> {
+    let address = AudioObjectPropertyAddress {
+        mSelector: kAudioDevicePropertyMute,
+        mScope: kAudioObjectPropertyScopeOutput,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let mut muted: u32 = 0;
+    let mut size = std::mem::size_of::<u32>() as u32;
+
+    let status = AudioObjectGetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        &mut muted as *mut u32 as *mut c_void,
+    );
+
+    // Не у всех устройств есть свойство mute
+    if status == 0 {
+        Some(muted != 0)
+    } else {
+        tracing::debug!("Failed to get device mute. Status: {}", status);
+        None
+    }
+}
+
+unsafe fn get_u32_property(device_id: u32, selector: u32, scope: u32) -> Option<u32> {
+    let address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: scope,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let mut value: u32 = 0;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = AudioObjectGetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        &mut value as *mut u32 as *mut c_void,
+    );
+
+    if status == 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Имя и тип устройства вывода
+unsafe fn get_output_device(device_id: u32) -> OutputDevice {
+    let address = AudioObjectPropertyAddress {
+        mSelector: kAudioObjectPropertyName,
+        mScope: kAudioObjectPropertyScopeGlobal,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let mut name: CFStringRef = std::ptr::null();
+    let mut size = std::mem::size_of::<CFStringRef>() as u32;
+    let status = AudioObjectGetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        &mut size,
+        &mut name as *mut CFStringRef as *mut c_void,
+    );
+
+    let name = if status == 0 && !name.is_null() {
+        CFString::wrap_under_create_rule(name).to_string()
+    } else {
+        tracing::warn!("Failed to get device name. Status: {}", status);
+        String::new()
+    };
+
+    let transport_type = get_u32_property(device_id, kAudioDevicePropertyTransportType, kAudioObjectPropertyScopeGlobal);
+    let data_source = get_u32_property(device_id, kAudioDevicePropertyDataSource, kAudioObjectPropertyScopeOutput);
+    let form_factor = match transport_type {
+        Some(kAudioDeviceTransportTypeHDMI | kAudioDeviceTransportTypeDisplayPort) => FormFactor::Hdmi,
+        Some(kAudioDeviceTransportTypeBluetooth | kAudioDeviceTransportTypeBluetoothLE) => FormFactor::Headphones,
+        // Встроенный выход переключается на наушники при их подключении
+        Some(kAudioDeviceTransportTypeBuiltIn) if data_source == Some(DATA_SOURCE_HEADPHONES) => FormFactor::Headphones,
+        Some(kAudioDeviceTransportTypeBuiltIn) => FormFactor::Speakers,
+        _ => FormFactor::Unknown,
+    };
+
+    OutputDevice { name, form_factor }
+}
+
+unsafe fn set_device_property<T>(device_id: u32, selector: u32, value: T) -> Result<(), String> {
+    let address = AudioObjectPropertyAddress {
+        mSelector: selector,
+        mScope: kAudioObjectPropertyScopeOutput,
+        mElement: kAudioObjectPropertyElementMaster,
+    };
+
+    let status = AudioObjectSetPropertyData(
+        device_id,
+        &address,
+        0,
+        std::ptr::null(),
+        std::mem::size_of::<T>() as u32,
+        &value as *const T as *const c_void,
+    );
+
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(format!("status {}", status))
+    }
+}
+
+/// Новое состояние отправляется при следующей проверке
+unsafe fn handle_keyboard_command(command: KeyboardCommand) -> Result<(), String> {
+    let device_id = get_default_output_device().ok_or("no default output device")?;
+    match command {
+        KeyboardCommand::ToggleMute => {
+            let muted = get_device_mute(device_id).ok_or("device does not support mute")?;
+            set_device_property(device_id, kAudioDevicePropertyMute, (!muted) as u32)
+        }
+        KeyboardCommand::SetMute(mute) => set_device_property(device_id, kAudioDevicePropertyMute, mute as u32),
+        KeyboardCommand::SetVolume(percent) => set_device_property(device_id, kAudioDevicePropertyVolumeScalar, percent as f32 / 100.0),
+    }
+}
+
+/// `forced` skips the threshold, so volume set to zero from keyboard is sent back
+fn send_data(volume: f32, muted: bool, forced: bool, data_sender: &mpsc::Sender<Vec<u8>>) {
     let volume_percentage = (volume * 100.0).round() as u8;
 
-    if volume_percentage > MIN_VOLUME_SEND_THRESHOLD {
-        let data = vec![DataType::Volume as u8, volume_percentage];
+    if forced || muted || volume_percentage > MIN_VOLUME_SEND_THRESHOLD {
+        let data = vec![DataType::Volume as u8, volume_percentage, muted as u8];
         match data_sender.try_send(data) {
-            Ok(_) => tracing::info!("Successfully sent volume data: {}%", volume_percentage),
+            Ok(_) => tracing::info!("Successfully sent volume data: {}%, muted: {}", volume_percentage, muted),
             Err(e) => tracing::error!("Failed to send volume data: {}", e),
         }
     } else {
@@ -82,71 +222,50 @@ fn send_data(volume: f32, data_sender: &mpsc::Sender<Vec<u8>>) {
 }
 
 pub struct VolumeProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: VolumeProviderConfig,
 }
 
 impl VolumeProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = VolumeProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: VolumeProviderConfig) -> Box<dyn Provider> {
+        let provider = VolumeProvider { config };
         Box::new(provider)
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for VolumeProvider {
-    fn start(&self) {
-        tracing::info!("Volume Provider started");
+    fn name(&self) -> &str {
+        "Volume"
+    }
 
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
+    async fn run(&self, mut ctx: ProviderContext) -> ProviderResult {
         let mut synced_volume = 0.0;
+        let mut synced_muted = false;
+        let mut forced = false;
+        let mut synced_device = None;
+        let max_length = self.config.max_length;
 
-        // Переменная для отслеживания статуса подключения
-        let is_connected = Arc::new(Mutex::new(true));
-        let is_connected_ref = is_connected.clone();
-
-        std::thread::spawn(move || {
-            let mut connected_receiver = connected_sender.subscribe();
-            loop {
-                // Проверяем подключение устройства
-                if !connected_receiver.try_recv().unwrap_or(true) {
-                    let mut is_connected = is_connected_ref.lock().unwrap();
-                    *is_connected = false;
-                    break;
-                }
-
-                std::thread::sleep(std::time::Duration::from_millis(100));
-            }
-        });
-
-        std::thread::spawn(move || {
-            loop {
-                if !*(is_connected.lock().unwrap()) {
-                    break;
-                }
-
+        run_blocking(move || {
+            while !ctx.cancellation_token.is_cancelled() {
                 unsafe {
                     if let Some(device_id) = get_default_output_device() {
+                        // При смене устройства громкость отправляется заново
+                        let device_changed = synced_device != Some(device_id);
+                        if device_changed {
+                            synced_device = Some(device_id);
+                            get_output_device(device_id).send(max_length, &ctx.data_sender);
+                        }
+
                         if let Some(volume) = get_device_volume(device_id) {
+                            let muted = get_device_mute(device_id).unwrap_or_default();
                             let volume_change = (volume - synced_volume).abs();
-                            if volume_change > MIN_VOLUME_CHANGE {
-                                tracing::debug!(
-                                    "Volume changed from {} to {}, change: {}",
-                                    synced_volume,
-                                    volume,
-                                    volume_change
-                                );
+                            if forced || device_changed || volume_change > MIN_VOLUME_CHANGE || muted != synced_muted {
+                                tracing::debug!("Volume changed from {} to {}, change: {}", synced_volume, volume, volume_change);
                                 synced_volume = volume;
-                                send_data(volume, &data_sender);
+                                synced_muted = muted;
+                                send_data(volume, muted, forced, &ctx.data_sender);
                             } else {
-                                tracing::debug!(
-                                    "Volume change too small: {} (threshold: {})",
-                                    volume_change,
-                                    MIN_VOLUME_CHANGE
-                                );
+                                tracing::debug!("Volume change too small: {} (threshold: {})", volume_change, MIN_VOLUME_CHANGE);
                             }
                         } else {
                             tracing::warn!("Failed to obtain volume for device ID: {}", device_id);
@@ -156,11 +275,26 @@ impl Provider for VolumeProvider {
                     }
                 }
 
-                // Задержка между проверками
-                std::thread::sleep(std::time::Duration::from_secs(2));
+                // Задержка между проверками, команды с клавиатуры выполняются сразу
+                forced = false;
+                for _ in 0..POLLS_PER_CHECK {
+                    while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+                        if let Some(command) = KeyboardCommand::parse(&data) {
+                            unsafe { handle_keyboard_command(command) }.unwrap_or_else(|e| tracing::warn!("Can not change volume: {}", e));
+                            forced = true;
+                        }
+                    }
+
+                    if forced || ctx.cancellation_token.is_cancelled() {
+                        break;
+                    }
+
+                    std::thread::sleep(POLL_INTERVAL);
+                }
             }
 
-            tracing::info!("Volume Provider stopped");
-        });
+            Ok(())
+        })
+        .await
     }
 }
@@ -1,34 +1,50 @@
-use tokio::sync::{
-    broadcast::{self, Receiver},
-    mpsc::{self, Sender},
-};
+use tokio::sync::mpsc;
 use windows::{
     core::Error,
     Win32::{
+        Devices::FunctionDiscovery::PKEY_Device_FriendlyName,
+        Foundation::BOOL,
         Media::Audio::{
-            eMultimedia, eRender,
+            eMultimedia, eRender, DigitalAudioDisplayDevice, EndpointFormFactor,
             Endpoints::{IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl},
-            IMMDeviceEnumerator, MMDeviceEnumerator, AUDIO_VOLUME_NOTIFICATION_DATA,
+            Headphones, Headset, IMMDevice, IMMDeviceEnumerator, LineLevel, MMDeviceEnumerator, PKEY_AudioEndpoint_FormFactor, Speakers,
+            AUDIO_VOLUME_NOTIFICATION_DATA,
         },
-        System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED},
+        System::Com::{CoCreateInstance, CoInitializeEx, CoTaskMemFree, CLSCTX_ALL, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED, STGM_READ},
     },
 };
 
-use crate::data_type::DataType;
+use crate::{config::VolumeProviderConfig, data_type::DataType};
 
-use super::super::_base::Provider;
+use super::super::_base::{run_blocking, Provider, ProviderContext, ProviderResult};
+use super::{FormFactor, KeyboardCommand, OutputDevice};
 
-fn get_volume() -> Result<f32, ()> {
-    let endpoint_volume = unsafe { get_volume_endpoint() }.map_err(|e| tracing::error!("Can not get volume endpoint: {}", e));
-    return unsafe { endpoint_volume?.GetMasterVolumeLevelScalar() }.map_err(|e| tracing::error!("Can not get volume level: {}", e));
-}
+/// Default device is checked every n-th iteration of the wait loop
+const DEVICE_CHECK_ITERATIONS: u32 = 10;
 
-unsafe fn get_volume_endpoint() -> Result<IAudioEndpointVolume, Error> {
+unsafe fn get_device_enumerator() -> Result<IMMDeviceEnumerator, Error> {
     let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
-    let instance: windows::core::Result<IMMDeviceEnumerator> = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_INPROC_SERVER);
-    return instance?
-        .GetDefaultAudioEndpoint(eRender, eMultimedia)?
-        .Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None);
+    return CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_INPROC_SERVER);
+}
+
+unsafe fn get_device_id(device: &IMMDevice) -> Result<String, Error> {
+    let id = device.GetId()?;
+    let result = id.to_string().unwrap_or_default();
+    CoTaskMemFree(Some(id.0 as *const _));
+    return Ok(result);
+}
+
+unsafe fn get_output_device(device: &IMMDevice) -> Result<OutputDevice, Error> {
+    let store = device.OpenPropertyStore(STGM_READ)?;
+    let name = store.GetValue(&PKEY_Device_FriendlyName)?.to_string();
+    let form_factor = u32::try_from(&store.GetValue(&PKEY_AudioEndpoint_FormFactor)?).unwrap_or_default();
+    let form_factor = match EndpointFormFactor(form_factor as i32) {
+        Speakers | LineLevel => FormFactor::Speakers,
+        Headphones | Headset => FormFactor::Headphones,
+        DigitalAudioDisplayDevice => FormFactor::Hdmi,
+        _ => FormFactor::Unknown,
+    };
+    return Ok(OutputDevice { name, form_factor });
 }
 
 #[windows::core::implement(IAudioEndpointVolumeCallback)]
@@ -38,73 +54,104 @@ struct VolumeChangeCallback {
 
 impl IAudioEndpointVolumeCallback_Impl for VolumeChangeCallback {
     fn OnNotify(&self, notification_data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<(), windows::core::Error> {
-        let volume = (unsafe { *notification_data }).fMasterVolume;
-        send_data(&volume, &self.push_sender);
+        let data = unsafe { *notification_data };
+        send_data(&data.fMasterVolume, data.bMuted.as_bool(), &self.push_sender);
         return Ok(());
     }
 }
 
-fn send_data(value: &f32, push_sender: &mpsc::Sender<Vec<u8>>) {
+fn send_data(value: &f32, muted: bool, push_sender: &mpsc::Sender<Vec<u8>>) {
     let volume = (value * 100.0).round() as u8;
-    let data = vec![DataType::Volume as u8, volume];
+    let data = vec![DataType::Volume as u8, volume, muted as u8];
     push_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
 }
 
+/// New state is sent by volume callback
+unsafe fn handle_keyboard_command(endpoint_volume: &IAudioEndpointVolume, command: KeyboardCommand) -> Result<(), Error> {
+    return match command {
+        KeyboardCommand::ToggleMute => endpoint_volume.SetMute(BOOL::from(!endpoint_volume.GetMute()?.as_bool()), std::ptr::null()),
+        KeyboardCommand::SetMute(mute) => endpoint_volume.SetMute(BOOL::from(mute), std::ptr::null()),
+        KeyboardCommand::SetVolume(percent) => endpoint_volume.SetMasterVolumeLevelScalar(percent as f32 / 100.0, std::ptr::null()),
+    };
+}
+
 pub struct VolumeProvider {
-    data_sender: mpsc::Sender<Vec<u8>>,
-    connected_sender: broadcast::Sender<bool>,
+    config: VolumeProviderConfig,
 }
 
 impl VolumeProvider {
-    pub fn new(data_sender: mpsc::Sender<Vec<u8>>, connected_sender: broadcast::Sender<bool>) -> Box<dyn Provider> {
-        let provider = VolumeProvider {
-            data_sender,
-            connected_sender,
-        };
+    pub fn new(config: VolumeProviderConfig) -> Box<dyn Provider> {
+        let provider = VolumeProvider { config };
         return Box::new(provider);
     }
 }
 
+#[async_trait::async_trait]
 impl Provider for VolumeProvider {
-    fn start(&self) {
-        tracing::info!("Volume Provider started");
-        if let Ok(volume) = get_volume() {
-            send_data(&volume, &self.data_sender);
-        }
-
-        let data_sender = self.data_sender.clone();
-        let connected_sender = self.connected_sender.clone();
-        std::thread::spawn(move || loop {
-            let connected_receiver = connected_sender.subscribe();
-            if subscribe_and_wait(data_sender.clone(), connected_receiver) {
-                tracing::info!("Volume Provider stopped");
-                break;
-            }
+    fn name(&self) -> &str {
+        return "Volume";
+    }
 
-            std::thread::sleep(std::time::Duration::from_millis(10000));
-        });
+    async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+        let max_length = self.config.max_length;
+        return run_blocking(move || subscribe_and_wait(ctx, max_length)).await;
     }
 }
 
-fn subscribe_and_wait(data_sender: Sender<Vec<u8>>, mut connected_receiver: Receiver<bool>) -> bool {
-    if let Ok(endpoint_volume) = unsafe { get_volume_endpoint() } {
-        let volume_callback: IAudioEndpointVolumeCallback = VolumeChangeCallback { push_sender: data_sender }.into();
-        if let Err(e) = unsafe { endpoint_volume.RegisterControlChangeNotify(&volume_callback) } {
-            tracing::error!("Can not register Volume callback: {}", e);
-            return false;
+/// Follows default device, subscription is moved to the new device when it is changed
+fn subscribe_and_wait(mut ctx: ProviderContext, max_length: usize) -> ProviderResult {
+    let enumerator = unsafe { get_device_enumerator() }.map_err(|e| format!("Can not get device enumerator: {}", e))?;
+    while !ctx.cancellation_token.is_cancelled() {
+        let device = unsafe { enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia) }
+            .map_err(|e| format!("Can not get default output device: {}", e))?;
+        let device_id = unsafe { get_device_id(&device) }.map_err(|e| format!("Can not get device id: {}", e))?;
+        unsafe { get_output_device(&device) }
+            .unwrap_or_else(|e| {
+                tracing::warn!("Can not get output device properties: {}", e);
+                OutputDevice::default()
+            })
+            .send(max_length, &ctx.data_sender);
+
+        let endpoint_volume = unsafe { device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None) }
+            .map_err(|e| format!("Can not get volume endpoint: {}", e))?;
+        if let Ok(volume) = unsafe { endpoint_volume.GetMasterVolumeLevelScalar() } {
+            let muted = unsafe { endpoint_volume.GetMute() }.map(|x| x.as_bool()).unwrap_or_default();
+            send_data(&volume, muted, &ctx.data_sender);
         }
 
-        loop {
-            if !connected_receiver.try_recv().unwrap_or(true) {
-                break;
+        let volume_callback: IAudioEndpointVolumeCallback = VolumeChangeCallback {
+            push_sender: ctx.data_sender.clone(),
+        }
+        .into();
+        unsafe { endpoint_volume.RegisterControlChangeNotify(&volume_callback) }
+            .map_err(|e| format!("Can not register Volume callback: {}", e))?;
+
+        let mut iteration: u32 = 0;
+        while !ctx.cancellation_token.is_cancelled() {
+            while let Ok(data) = ctx.keyboard_receiver.try_recv() {
+                if let Some(command) = KeyboardCommand::parse(&data) {
+                    unsafe { handle_keyboard_command(&endpoint_volume, command) }
+                        .unwrap_or_else(|e| tracing::warn!("Can not change volume: {}", e));
+                }
+            }
+
+            iteration = iteration.wrapping_add(1);
+            if iteration % DEVICE_CHECK_ITERATIONS == 0 {
+                let default_id = unsafe {
+                    enumerator
+                        .GetDefaultAudioEndpoint(eRender, eMultimedia)
+                        .and_then(|x| get_device_id(&x))
+                };
+                if default_id.is_ok_and(|x| x != device_id) {
+                    break;
+                }
             }
 
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
         let _ = unsafe { endpoint_volume.UnregisterControlChangeNotify(&volume_callback) };
-        return true;
     }
 
-    return false;
+    return Ok(());
 }
@@ -0,0 +1,357 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, INT};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    config::{CommandProviderConfig, PayloadFormat, ScriptConfig},
+    data_type::DataType,
+};
+
+const FUNCTION_NAME: &str = "on_message";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+/// Limits scripts with endless loops, every message gets a fresh budget
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Message types with `[type, ...data, length, ...utf8]` payload by number of `data` bytes before the text, scripts get the text as `text`
+type TextLayouts = BTreeMap<u8, usize>;
+
+fn get_text_layouts(commands: &[CommandProviderConfig]) -> TextLayouts {
+    let mut layouts = DataType::ALL
+        .into_iter()
+        .filter_map(|x| {
+            let offset = match x {
+                DataType::MediaArtist | DataType::MediaTitle => 0,
+                DataType::ZoneTime => 4,
+                DataType::CalendarEvent => 2,
+                DataType::CalendarAlert | DataType::OutputDevice => 1,
+                _ => return None,
+            };
+            Some((x as u8, offset))
+        })
+        .collect::<TextLayouts>();
+    layouts.extend(
+        commands
+            .iter()
+            .filter(|x| x.payload == PayloadFormat::Text)
+            .map(|x| (x.message_type, 0)),
+    );
+    return layouts;
+}
+
+/// `MediaArtist` is available to scripts as `types::MEDIA_ARTIST`
+fn get_constant_name(data_type: DataType) -> String {
+    let mut name = String::new();
+    for (index, char) in format!("{:?}", data_type).chars().enumerate() {
+        if char.is_uppercase() && index > 0 {
+            name.push('_');
+        }
+
+        name.push(char.to_ascii_uppercase());
+    }
+
+    return name;
+}
+
+fn create_engine(state: Arc<Mutex<Map>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let mut types = rhai::Module::new();
+    for data_type in DataType::ALL {
+        types.set_var(get_constant_name(data_type), data_type as INT);
+    }
+    engine.register_static_module("types", types.into());
+
+    let get_state = state.clone();
+    engine.register_fn("state_get", move |key: &str| -> Dynamic {
+        return get_state.lock().ok().and_then(|x| x.get(key).cloned()).unwrap_or_default();
+    });
+    engine.register_fn("state_set", move |key: &str, value: Dynamic| {
+        if let Ok(mut state) = state.lock() {
+            state.insert(key.into(), value);
+        }
+    });
+
+    engine.on_print(|x| tracing::info!("Script: {}", x));
+    engine.on_debug(|x, _, _| tracing::debug!("Script: {}", x));
+
+    return engine;
+}
+
+fn to_array(data: &[u8]) -> Dynamic {
+    return data.iter().map(|x| Dynamic::from(*x as INT)).collect::<Array>().into();
+}
+
+/// Text messages get `data` with bytes before the text and `text`, other messages get all bytes after the type as `data`
+fn to_message(data: &[u8], text_layouts: &TextLayouts) -> Map {
+    let mut message = Map::new();
+    let message_type = data.first().copied().unwrap_or_default();
+    message.insert("type".into(), (message_type as INT).into());
+    let payload = data.get(1..).unwrap_or_default();
+    match text_layouts.get(&message_type) {
+        Some(offset) if payload.len() > *offset => {
+            let text = &payload[offset + 1..];
+            let length = (payload[*offset] as usize).min(text.len());
+            message.insert("data".into(), to_array(&payload[..*offset]));
+            message.insert("text".into(), String::from_utf8_lossy(&text[..length]).to_string().into());
+        }
+        _ => {
+            message.insert("data".into(), to_array(payload));
+        }
+    }
+
+    return message;
+}
+
+fn from_message(message: Map) -> Result<Vec<u8>, String> {
+    let message_type = message
+        .get("type")
+        .and_then(|x| x.as_int().ok())
+        .and_then(|x| u8::try_from(x).ok())
+        .ok_or("message type must be a number 0-255")?;
+
+    let mut data = vec![message_type];
+    if let Some(payload) = message.get("data") {
+        if payload.is_blob() {
+            data.extend(payload.clone().into_blob().unwrap_or_default());
+        } else {
+            let payload = payload.clone().into_array().map_err(|_| "message data must be an array")?;
+            for x in payload {
+                data.push(
+                    x.as_int()
+                        .ok()
+                        .and_then(|x| u8::try_from(x).ok())
+                        .ok_or("message data must contain numbers 0-255")?,
+                );
+            }
+        }
+    }
+
+    if let Some(text) = message.get("text") {
+        let mut text = text.to_string().into_bytes();
+        text.truncate(u8::MAX as usize);
+        data.push(text.len() as u8);
+        data.extend(text);
+    }
+
+    return Ok(data);
+}
+
+/// Converts script result to messages, `()` drops the message, array splits it
+fn from_result(result: Dynamic) -> Result<Vec<Vec<u8>>, String> {
+    if result.is_unit() {
+        return Ok(vec![]);
+    }
+
+    if result.is_array() {
+        return result
+            .into_array()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| !x.is_unit())
+            .map(|x| {
+                x.try_cast::<Map>()
+                    .ok_or("array must contain messages".to_string())
+                    .and_then(from_message)
+            })
+            .collect();
+    }
+
+    let message = result
+        .try_cast::<Map>()
+        .ok_or("result must be a message, array of messages or ()")?;
+    return Ok(vec![from_message(message)?]);
+}
+
+fn get_modified(path: &str) -> Option<SystemTime> {
+    return std::fs::metadata(path).and_then(|x| x.modified()).ok();
+}
+
+/// User script between providers and keyboard, which can rewrite, drop or split every message
+pub struct Script {
+    path: String,
+    text_layouts: TextLayouts,
+    engine: Engine,
+    ast: Option<AST>,
+    modified: Option<SystemTime>,
+}
+
+impl Script {
+    /// Text commands are registered, so scripts get their payload as `text`
+    pub fn new(config: ScriptConfig, commands: &[CommandProviderConfig]) -> Self {
+        let mut script = Self {
+            path: config.path,
+            text_layouts: get_text_layouts(commands),
+            engine: create_engine(Arc::new(Mutex::new(Map::new()))),
+            ast: None,
+            modified: None,
+        };
+        script.reload();
+        return script;
+    }
+
+    /// Compiles script again if file was changed, previous version is kept if new one has errors
+    fn reload(&mut self) {
+        let modified = get_modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+
+        self.modified = modified;
+        if modified.is_none() {
+            if self.ast.take().is_some() {
+                tracing::info!("Script {} removed", self.path);
+            }
+
+            return;
+        }
+
+        match self.engine.compile_file(self.path.clone().into()) {
+            Ok(ast) if ast.iter_functions().any(|x| x.name == FUNCTION_NAME && x.params.len() == 1) => {
+                tracing::info!("Script {} loaded", self.path);
+                self.ast = Some(ast);
+            }
+            Ok(_) => tracing::error!("Script {} must define function {}(message)", self.path, FUNCTION_NAME),
+            Err(e) => tracing::error!("Can not load script {}: {}", self.path, e),
+        }
+    }
+
+    /// Script errors are logged and original message is sent as is
+    fn transform(&self, data: Vec<u8>) -> Vec<Vec<u8>> {
+        let Some(ast) = &self.ast else {
+            return vec![data];
+        };
+
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                ast,
+                FUNCTION_NAME,
+                (to_message(&data, &self.text_layouts),),
+            )
+            .map_err(|e| e.to_string())
+            .and_then(from_result);
+
+        return match result {
+            Ok(messages) => messages,
+            Err(e) => {
+                tracing::error!("Script failed on {:?}: {}", data, e);
+                vec![data]
+            }
+        };
+    }
+
+    /// Returns sender for providers, transformed messages are passed to `data_sender`.
+    /// Returned task finishes after all senders are dropped and queued messages are passed on
+    pub fn connect(mut self, data_sender: mpsc::Sender<Vec<u8>>) -> (mpsc::Sender<Vec<u8>>, JoinHandle<()>) {
+        let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(32);
+        let task = tokio::spawn(async move {
+            let mut reload_interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = reload_interval.tick() => self.reload(),
+                    received = receiver.recv() => {
+                        let Some(received) = received else {
+                            break;
+                        };
+
+                        for data in self.transform(received) {
+                            data_sender.try_send(data).unwrap_or_else(|e| tracing::error!("{}", e));
+                        }
+                    }
+                }
+            }
+        });
+
+        return (sender, task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_command(message_type: u8, payload: &str) -> CommandProviderConfig {
+        return serde_json::from_value(
+            serde_json::json!({ "name": "Test", "command": "true", "messageType": message_type, "payload": payload }),
+        )
+        .unwrap();
+    }
+
+    fn round_trip(data: &[u8]) -> Map {
+        let message = to_message(data, &get_text_layouts(&[]));
+        assert_eq!(from_message(message.clone()).unwrap(), data);
+        return message;
+    }
+
+    #[test]
+    fn every_type_is_registered() {
+        assert_eq!(get_constant_name(DataType::Time), "TIME");
+        assert_eq!(get_constant_name(DataType::MediaArtist), "MEDIA_ARTIST");
+        assert_eq!(get_constant_name(DataType::OutputDevice), "OUTPUT_DEVICE");
+
+        let engine = create_engine(Arc::new(Mutex::new(Map::new())));
+        for data_type in DataType::ALL {
+            let value = engine.eval::<INT>(&format!("types::{}", get_constant_name(data_type))).unwrap();
+            assert_eq!(value, data_type as INT);
+        }
+    }
+
+    #[test]
+    fn media_text_is_converted() {
+        let message = round_trip(&[DataType::MediaTitle as u8, 3, b'a', b'b', b'c']);
+        assert_eq!(message["text"].to_string(), "abc");
+        assert!(message["data"].clone().into_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn text_after_data_is_converted() {
+        let message = round_trip(&[DataType::ZoneTime as u8, 1, 9, 30, 0, 3, b'N', b'Y', b'C']);
+        assert_eq!(message["text"].to_string(), "NYC");
+        assert_eq!(message["data"].clone().into_array().unwrap().len(), 4);
+
+        let message = round_trip(&[DataType::CalendarEvent as u8, 10, 0, 2, b'h', b'i']);
+        assert_eq!(message["text"].to_string(), "hi");
+
+        let message = round_trip(&[DataType::OutputDevice as u8, 2, 1, b'x']);
+        assert_eq!(message["text"].to_string(), "x");
+        assert_eq!(message["data"].clone().into_array().unwrap()[0].as_int(), Ok(2));
+    }
+
+    #[test]
+    fn changed_text_gets_new_length() {
+        let mut message = to_message(&[DataType::CalendarAlert as u8, 5, 2, b'h', b'i'], &get_text_layouts(&[]));
+        message.insert("text".into(), "hello".into());
+        assert_eq!(
+            from_message(message).unwrap(),
+            vec![DataType::CalendarAlert as u8, 5, 5, b'h', b'e', b'l', b'l', b'o']
+        );
+    }
+
+    #[test]
+    fn bytes_are_converted() {
+        let message = round_trip(&[DataType::Volume as u8, 50, 1]);
+        assert!(!message.contains_key("text"));
+        round_trip(&[DataType::HostDisconnected as u8]);
+        // Text message too short for its layout is passed as bytes
+        let message = round_trip(&[DataType::ZoneTime as u8, 1, 9]);
+        assert!(!message.contains_key("text"));
+    }
+
+    #[test]
+    fn text_commands_are_converted() {
+        let layouts = get_text_layouts(&[create_command(0xD0, "text"), create_command(0xD1, "bytes")]);
+        let message = to_message(&[0xD0, 2, b'o', b'k'], &layouts);
+        assert_eq!(message["text"].to_string(), "ok");
+        let message = to_message(&[0xD1, 2, b'o', b'k'], &layouts);
+        assert!(!message.contains_key("text"));
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{broadcast, mpsc, Semaphore},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::providers::_base::{Provider, ProviderContext, INSTANCE};
+
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// Provider that worked at least this long before failing is restarted with minimal delay again
+const STABLE_RUN_DURATION: Duration = Duration::from_secs(60);
+/// Providers that do not return after cancellation within this time are abandoned, so reconnect and shutdown are not blocked
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProviderState {
+    #[default]
+    Stopped,
+    Running,
+    Restarting,
+}
+
+#[derive(Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderHealth {
+    pub state: ProviderState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Shared per-provider health state, updated by supervisor
+#[derive(Clone, Default)]
+pub struct Health(Arc<Mutex<BTreeMap<String, ProviderHealth>>>);
+
+impl Health {
+    pub fn snapshot(&self) -> BTreeMap<String, ProviderHealth> {
+        return self.0.lock().map(|x| x.clone()).unwrap_or_default();
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ProviderHealth)) {
+        if let Ok(mut health) = self.0.lock() {
+            f(health.entry(name.to_string()).or_default());
+        }
+    }
+}
+
+fn get_panic_message(e: tokio::task::JoinError) -> String {
+    if !e.is_panic() {
+        return e.to_string();
+    }
+
+    let panic = e.into_panic();
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|x| x.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    return format!("panicked: {}", message);
+}
+
+/// Delay before the next restart, doubled after every failure and reset after a stable run
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        return Self { delay: MIN_RESTART_DELAY };
+    }
+
+    fn next(&mut self, run_duration: Duration) -> Duration {
+        if run_duration >= STABLE_RUN_DURATION {
+            self.delay = MIN_RESTART_DELAY;
+        }
+
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_RESTART_DELAY);
+        return delay;
+    }
+}
+
+/// Aborts provider task when its supervising task is aborted
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs provider until cancelled, restarting it with exponential backoff after errors and panics.
+/// A new instance is started only after the previous one has released `instance_lock`
+async fn supervise(
+    provider: Arc<dyn Provider>,
+    instance_lock: Arc<Semaphore>,
+    data_sender: mpsc::Sender<Vec<u8>>,
+    keyboard_sender: broadcast::Sender<Vec<u8>>,
+    resume_sender: broadcast::Sender<()>,
+    cancellation_token: CancellationToken,
+    health: Health,
+) {
+    let name = provider.name();
+    let mut backoff = Backoff::new();
+    loop {
+        let instance = match instance_lock.clone().try_acquire_owned() {
+            Ok(instance) => instance,
+            Err(_) => {
+                tracing::warn!("{} Provider waits for the previous instance to finish", name);
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    instance = instance_lock.clone().acquire_owned() => match instance {
+                        Ok(instance) => instance,
+                        Err(_) => break,
+                    },
+                }
+            }
+        };
+
+        tracing::info!("{} Provider started", name);
+        health.update(name, |x| x.state = ProviderState::Running);
+        let started_at = Instant::now();
+
+        let ctx = ProviderContext {
+            data_sender: data_sender.clone(),
+            keyboard_receiver: keyboard_sender.subscribe(),
+            resume_sender: resume_sender.clone(),
+            cancellation_token: cancellation_token.child_token(),
+        };
+        let task_provider = provider.clone();
+        let mut task = AbortOnDrop(tokio::spawn(INSTANCE.scope(Arc::new(instance), async move {
+            return task_provider.run(ctx).await;
+        })));
+        let result = (&mut task.0).await;
+
+        if cancellation_token.is_cancelled() {
+            break;
+        }
+
+        let error = match result {
+            Ok(Ok(())) => "stopped unexpectedly".to_string(),
+            Ok(Err(e)) => e,
+            Err(e) => get_panic_message(e),
+        };
+
+        let restart_delay = backoff.next(started_at.elapsed());
+        tracing::error!("{} Provider failed: {}, restarting in {:?}", name, error, restart_delay);
+        health.update(name, |x| {
+            x.state = ProviderState::Restarting;
+            x.restarts += 1;
+            x.last_error = Some(error);
+        });
+
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = tokio::time::sleep(restart_delay) => (),
+        }
+    }
+
+    health.update(name, |x| x.state = ProviderState::Stopped);
+    tracing::info!("{} Provider stopped", name);
+}
+
+struct Running {
+    cancellation_token: CancellationToken,
+    tasks: Vec<(String, JoinHandle<()>)>,
+}
+
+/// Starts providers when keyboard is connected and stops them on disconnect.
+/// Providers are restarted only after all previous instances have finished, so there is never more than one instance of each.
+pub struct Supervisor {
+    /// Providers with their single instance locks
+    providers: Vec<(Arc<dyn Provider>, Arc<Semaphore>)>,
+    data_sender: mpsc::Sender<Vec<u8>>,
+    keyboard_sender: broadcast::Sender<Vec<u8>>,
+    resume_sender: broadcast::Sender<()>,
+    running: Option<Running>,
+    health: Health,
+    stop_timeout: Duration,
+}
+
+impl Supervisor {
+    /// Provider names must be unique (case-insensitive), as health state is keyed by them
+    pub fn new(
+        providers: Vec<Box<dyn Provider>>,
+        data_sender: mpsc::Sender<Vec<u8>>,
+        keyboard_sender: broadcast::Sender<Vec<u8>>,
+    ) -> Result<Self, String> {
+        let mut names = std::collections::HashSet::new();
+        if let Some(provider) = providers.iter().find(|x| !names.insert(x.name().to_lowercase())) {
+            return Err(format!("provider name {} is used more than once", provider.name()));
+        }
+
+        let health = Health::default();
+        providers.iter().for_each(|x| health.update(x.name(), |_| ()));
+        return Ok(Self {
+            providers: providers.into_iter().map(|x| (Arc::from(x), Arc::new(Semaphore::new(1)))).collect(),
+            data_sender,
+            keyboard_sender,
+            resume_sender: broadcast::channel(1).0,
+            running: None,
+            health,
+            stop_timeout: STOP_TIMEOUT,
+        });
+    }
+
+    pub fn health(&self) -> Health {
+        return self.health.clone();
+    }
+
+    fn start(&mut self) {
+        if self.running.is_some() {
+            return;
+        }
+
+        let cancellation_token = CancellationToken::new();
+        let tasks = self
+            .providers
+            .iter()
+            .map(|(provider, instance_lock)| {
+                let task = tokio::spawn(supervise(
+                    provider.clone(),
+                    instance_lock.clone(),
+                    self.data_sender.clone(),
+                    self.keyboard_sender.clone(),
+                    self.resume_sender.clone(),
+                    cancellation_token.clone(),
+                    self.health.clone(),
+                ));
+                (provider.name().to_string(), task)
+            })
+            .collect();
+
+        self.running = Some(Running { cancellation_token, tasks });
+    }
+
+    /// Waits for all providers to finish, but not longer than `stop_timeout` in total.
+    /// Abandoned provider is aborted, but its blocking code (like hung D-Bus or COM call) keeps running on its thread until it returns
+    /// and the provider is not started again until then
+    async fn stop(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.cancellation_token.cancel();
+            let deadline = tokio::time::Instant::now() + self.stop_timeout;
+            for (name, mut task) in running.tasks {
+                if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                    tracing::error!("{} Provider did not stop in {:?}, abandoning it", name, self.stop_timeout);
+                    task.abort();
+                    self.health.update(&name, |x| {
+                        x.state = ProviderState::Stopped;
+                        x.last_error = Some("did not stop after cancellation".to_string());
+                    });
+                }
+            }
+        }
+    }
+
+    /// Runs until `shutdown` is cancelled or keyboard thread stops, providers are stopped before returning
+    pub async fn run(mut self, mut connected_receiver: broadcast::Receiver<bool>, shutdown: CancellationToken) {
+        loop {
+            let connected = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                x = connected_receiver.recv() => x,
+            };
+
+            match connected {
+                Ok(true) => self.start(),
+                Ok(false) => self.stop().await,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        self.stop().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::providers::_base::{run_blocking, ProviderResult};
+
+    struct TestProvider {
+        name: &'static str,
+        result: fn() -> ProviderResult,
+        ignores_cancellation: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for TestProvider {
+        fn name(&self) -> &str {
+            return self.name;
+        }
+
+        async fn run(&self, ctx: ProviderContext) -> ProviderResult {
+            if self.ignores_cancellation {
+                std::future::pending::<()>().await;
+            }
+
+            let result = (self.result)();
+            if result.is_ok() {
+                ctx.cancellation_token.cancelled().await;
+            }
+
+            return result;
+        }
+    }
+
+    /// Ignores cancellation and counts its running instances, blocking one keeps its thread busy for `BLOCKING_DURATION`
+    #[derive(Clone, Default)]
+    struct InstanceProvider {
+        blocking: bool,
+        instances: Arc<AtomicUsize>,
+        started: Arc<AtomicUsize>,
+        overlapped: Arc<AtomicBool>,
+    }
+
+    const BLOCKING_DURATION: Duration = Duration::from_millis(500);
+
+    struct InstanceGuard(Arc<AtomicUsize>);
+
+    impl Drop for InstanceGuard {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for InstanceProvider {
+        fn name(&self) -> &str {
+            return "Instance";
+        }
+
+        async fn run(&self, _ctx: ProviderContext) -> ProviderResult {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            if self.instances.fetch_add(1, Ordering::SeqCst) > 0 {
+                self.overlapped.store(true, Ordering::SeqCst);
+            }
+
+            let guard = InstanceGuard(self.instances.clone());
+            if self.blocking {
+                return run_blocking(move || {
+                    let _guard = guard;
+                    std::thread::sleep(BLOCKING_DURATION);
+                    return Ok(());
+                })
+                .await;
+            }
+
+            let _guard = guard;
+            std::future::pending::<()>().await;
+            return Ok(());
+        }
+    }
+
+    fn create_supervisor(providers: Vec<Box<dyn Provider>>) -> Supervisor {
+        return Supervisor::new(providers, mpsc::channel(10).0, broadcast::channel(10).0).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_delay_up_to_max() {
+        let mut backoff = Backoff::new();
+        let delays = (0..9).map(|_| backoff.next(Duration::ZERO).as_secs()).collect::<Vec<_>>();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60, 60]);
+    }
+
+    #[test]
+    fn backoff_is_reset_after_stable_run() {
+        let mut backoff = Backoff::new();
+        (0..5).for_each(|_| {
+            backoff.next(Duration::from_secs(1));
+        });
+        assert_eq!(backoff.next(STABLE_RUN_DURATION), MIN_RESTART_DELAY);
+        assert_eq!(backoff.next(Duration::from_secs(1)), MIN_RESTART_DELAY * 2);
+    }
+
+    #[test]
+    fn duplicate_provider_names_are_rejected() {
+        let providers: Vec<Box<dyn Provider>> = ["Time", "time"]
+            .into_iter()
+            .map(|name| -> Box<dyn Provider> {
+                Box::new(TestProvider {
+                    name,
+                    result: || Ok(()),
+                    ignores_cancellation: false,
+                })
+            })
+            .collect();
+        assert!(Supervisor::new(providers, mpsc::channel(10).0, broadcast::channel(10).0).is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_provider_is_restarting_and_others_keep_running() {
+        let mut supervisor = create_supervisor(vec![
+            Box::new(TestProvider {
+                name: "Failing",
+                result: || Err("broken".to_string()),
+                ignores_cancellation: false,
+            }),
+            Box::new(TestProvider {
+                name: "Working",
+                result: || Ok(()),
+                ignores_cancellation: false,
+            }),
+        ]);
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let health = supervisor.health().snapshot();
+        assert!(matches!(health["Failing"].state, ProviderState::Restarting));
+        assert_eq!(health["Failing"].restarts, 1);
+        assert_eq!(health["Failing"].last_error.as_deref(), Some("broken"));
+        assert!(matches!(health["Working"].state, ProviderState::Running));
+        assert_eq!(health["Working"].restarts, 0);
+
+        supervisor.stop().await;
+        let health = supervisor.health().snapshot();
+        assert!(health.values().all(|x| matches!(x.state, ProviderState::Stopped)));
+    }
+
+    #[tokio::test]
+    async fn stop_does_not_wait_for_hung_provider() {
+        let mut supervisor = create_supervisor(vec![Box::new(TestProvider {
+            name: "Hung",
+            result: || Ok(()),
+            ignores_cancellation: true,
+        })]);
+        supervisor.stop_timeout = Duration::from_millis(100);
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let started_at = Instant::now();
+        supervisor.stop().await;
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+
+        let health = supervisor.health().snapshot();
+        assert!(matches!(health["Hung"].state, ProviderState::Stopped));
+        assert!(health["Hung"].last_error.is_some());
+        assert!(supervisor.running.is_none());
+    }
+
+    #[tokio::test]
+    async fn hung_provider_is_aborted_on_stop() {
+        let provider = InstanceProvider::default();
+        let mut supervisor = create_supervisor(vec![Box::new(provider.clone())]);
+        supervisor.stop_timeout = Duration::from_millis(100);
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        supervisor.stop().await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(provider.instances.load(Ordering::SeqCst), 0);
+
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(provider.started.load(Ordering::SeqCst), 2);
+        assert_eq!(provider.instances.load(Ordering::SeqCst), 1);
+        assert!(!provider.overlapped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hung_blocking_provider_is_not_started_twice() {
+        let provider = InstanceProvider {
+            blocking: true,
+            ..Default::default()
+        };
+        let mut supervisor = create_supervisor(vec![Box::new(provider.clone())]);
+        supervisor.stop_timeout = Duration::from_millis(100);
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        supervisor.stop().await;
+
+        supervisor.start();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(provider.started.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(BLOCKING_DURATION).await;
+        assert_eq!(provider.started.load(Ordering::SeqCst), 2);
+        assert_eq!(provider.instances.load(Ordering::SeqCst), 1);
+        assert!(!provider.overlapped.load(Ordering::SeqCst));
+    }
+}
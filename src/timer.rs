@@ -0,0 +1,347 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{broadcast, watch};
+
+use crate::config::TimerConfig;
+
+/// Commands sent by keyboard as `[type, command, minutes]`
+const KEYBOARD_STOP: u8 = 0;
+const KEYBOARD_POMODORO: u8 = 1;
+const KEYBOARD_COUNTDOWN: u8 = 2;
+const KEYBOARD_PAUSE: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TimerPhase {
+    #[default]
+    Idle = 0,
+    Work = 1,
+    Break = 2,
+    Countdown = 3,
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerStatus {
+    pub phase: TimerPhase,
+    /// Rounded up, so a new phase starts with its full duration and `0` is never shown while running
+    pub remaining_seconds: u32,
+    pub paused: bool,
+}
+
+#[derive(Default)]
+struct TimerState {
+    phase: TimerPhase,
+    remaining: Duration,
+    /// Set while running, `remaining` is counted from this moment
+    started_at: Option<Instant>,
+    /// Completed work phases, every `longBreakInterval`-th break is long
+    completed_work: u32,
+}
+
+impl TimerState {
+    fn get_remaining(&self) -> Duration {
+        return match self.started_at {
+            Some(started_at) => self.remaining.saturating_sub(started_at.elapsed()),
+            None => self.remaining,
+        };
+    }
+
+    fn start(&mut self, phase: TimerPhase, duration: Duration) {
+        self.phase = phase;
+        self.remaining = duration;
+        self.started_at = Some(Instant::now());
+    }
+}
+
+/// Longest phase, so remaining seconds fit into status
+const MAX_DURATION: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// `None` if duration is longer than `MAX_DURATION`
+fn minutes(value: u64) -> Option<Duration> {
+    return value.checked_mul(60).map(Duration::from_secs).filter(|x| *x <= MAX_DURATION);
+}
+
+/// Pomodoro and countdown timer shared by time provider, control server and keyboard commands.
+/// Phases are switched by `run` task, so timer keeps running while keyboard is disconnected or time provider is stopped
+#[derive(Clone)]
+pub struct Timer {
+    config: TimerConfig,
+    state: Arc<Mutex<TimerState>>,
+    changed: Arc<watch::Sender<()>>,
+    /// Finished and next phases
+    finished: broadcast::Sender<(TimerPhase, TimerPhase)>,
+}
+
+impl Timer {
+    pub fn new(config: TimerConfig) -> Self {
+        return Self {
+            config,
+            state: Arc::new(Mutex::new(TimerState::default())),
+            changed: Arc::new(watch::channel(()).0),
+            finished: broadcast::channel(8).0,
+        };
+    }
+
+    fn update_state<T>(&self, f: impl FnOnce(&mut TimerState) -> T) -> T {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        return f(&mut state);
+    }
+
+    /// Receiver is marked changed when timer was started, paused, stopped or switched to the next phase,
+    /// so the new state can be sent immediately
+    pub fn subscribe_changes(&self) -> watch::Receiver<()> {
+        return self.changed.subscribe();
+    }
+
+    /// Receives finished and next phases, phases finished before subscribing are not received
+    pub fn subscribe_finished(&self) -> broadcast::Receiver<(TimerPhase, TimerPhase)> {
+        return self.finished.subscribe();
+    }
+
+    pub fn start_pomodoro(&self) {
+        let duration = minutes(self.config.work_duration).unwrap_or(MAX_DURATION);
+        self.update_state(|x| {
+            x.completed_work = 0;
+            x.start(TimerPhase::Work, duration);
+        });
+        self.changed.send_replace(());
+    }
+
+    pub fn start_countdown(&self, duration: Duration) {
+        self.update_state(|x| x.start(TimerPhase::Countdown, duration));
+        self.changed.send_replace(());
+    }
+
+    pub fn stop(&self) {
+        self.update_state(|x| *x = TimerState::default());
+        self.changed.send_replace(());
+    }
+
+    /// Pauses running timer or resumes paused one
+    pub fn toggle_pause(&self) {
+        self.update_state(|x| {
+            if x.phase == TimerPhase::Idle {
+                return;
+            }
+
+            x.remaining = x.get_remaining();
+            x.started_at = match x.started_at {
+                Some(_) => None,
+                None => Some(Instant::now()),
+            };
+        });
+        self.changed.send_replace(());
+    }
+
+    pub fn status(&self) -> TimerStatus {
+        return self.update_state(|x| {
+            let remaining = x.get_remaining();
+            TimerStatus {
+                phase: x.phase,
+                remaining_seconds: (remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64) as u32,
+                paused: x.phase != TimerPhase::Idle && x.started_at.is_none(),
+            }
+        });
+    }
+
+    /// Time until remaining seconds change, `None` if timer is not running
+    pub fn get_next_tick(&self) -> Option<Duration> {
+        return self.update_state(|x| {
+            x.started_at?;
+            let nanos = x.get_remaining().subsec_nanos();
+            Some(Duration::from_nanos(if nanos == 0 { 1_000_000_000 } else { nanos as u64 }))
+        });
+    }
+
+    /// Finishes the current phase if its time is over, returns finished and next phases.
+    /// Pomodoro switches between work and breaks until stopped, countdown stops
+    fn update(&self) -> Option<(TimerPhase, TimerPhase)> {
+        let phases = self.update_state(|x| {
+            if x.started_at.is_none() || !x.get_remaining().is_zero() {
+                return None;
+            }
+
+            let finished = x.phase;
+            match finished {
+                TimerPhase::Work => {
+                    x.completed_work += 1;
+                    let is_long = self.config.long_break_interval > 0 && x.completed_work % self.config.long_break_interval == 0;
+                    let duration = if is_long {
+                        self.config.long_break_duration
+                    } else {
+                        self.config.break_duration
+                    };
+                    x.start(TimerPhase::Break, minutes(duration).unwrap_or(MAX_DURATION));
+                }
+                TimerPhase::Break => x.start(TimerPhase::Work, minutes(self.config.work_duration).unwrap_or(MAX_DURATION)),
+                TimerPhase::Countdown | TimerPhase::Idle => *x = TimerState::default(),
+            }
+
+            Some((finished, x.phase))
+        });
+        if phases.is_some() {
+            self.changed.send_replace(());
+        }
+
+        return phases;
+    }
+
+    /// Switches phases when their time is over, independent of providers and keyboard connection
+    pub async fn run(self) {
+        let mut changed = self.changed.subscribe();
+        loop {
+            let remaining = self.update_state(|x| x.started_at.map(|_| x.get_remaining()));
+            match remaining {
+                Some(remaining) => tokio::select! {
+                    _ = tokio::time::sleep(remaining) => (),
+                    _ = changed.changed() => (),
+                },
+                None => {
+                    let _ = changed.changed().await;
+                }
+            }
+
+            if let Some((finished, next)) = self.update() {
+                tracing::info!("Timer {:?} phase finished", finished);
+                let _ = self.finished.send((finished, next));
+                self.notify(finished);
+            }
+        }
+    }
+
+    /// Shows desktop notification about finished phase if enabled, errors are only logged
+    fn notify(&self, finished: TimerPhase) {
+        if !self.config.notifications {
+            return;
+        }
+
+        let body = match finished {
+            TimerPhase::Work => "Work is done, time for a break",
+            TimerPhase::Break => "Break is over, back to work",
+            TimerPhase::Countdown | TimerPhase::Idle => "Time is up",
+        };
+        tokio::task::spawn_blocking(move || {
+            notify_rust::Notification::new()
+                .summary("QMK HID Host")
+                .body(body)
+                .show()
+                .map(|_| ())
+                .unwrap_or_else(|e| tracing::warn!("Can not show notification: {}", e));
+        });
+    }
+
+    /// Handles `[type, command, minutes]` report from keyboard
+    pub fn handle_keyboard_command(&self, data: &[u8]) {
+        match data.get(1).copied() {
+            Some(KEYBOARD_STOP) => self.stop(),
+            Some(KEYBOARD_POMODORO) => self.start_pomodoro(),
+            Some(KEYBOARD_COUNTDOWN) => {
+                let value = data.get(2).copied().unwrap_or_default().max(1);
+                self.start_countdown(minutes(value as u64).unwrap_or(MAX_DURATION));
+            }
+            Some(KEYBOARD_PAUSE) => self.toggle_pause(),
+            x => tracing::warn!("Unknown timer command from keyboard: {:?}", x),
+        }
+    }
+
+    /// Handles `timer` command of control server: `pomodoro`, `start <minutes>`, `pause`, `stop` or `status`, returns status as JSON
+    pub fn handle_command(&self, args: &str) -> Result<String, String> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next()) {
+            (Some("pomodoro"), None) => self.start_pomodoro(),
+            (Some("start"), Some(value)) => {
+                let duration = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .and_then(minutes)
+                    .ok_or(format!("invalid minutes {}", value))?;
+                self.start_countdown(duration);
+            }
+            (Some("pause"), None) => self.toggle_pause(),
+            (Some("stop"), None) => self.stop(),
+            (Some("status") | None, None) => (),
+            _ => return Err("usage: timer pomodoro | start <minutes> | pause | stop | status".to_string()),
+        }
+
+        return serde_json::to_string(&self.status()).map_err(|e| e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn countdown_finishes_without_providers() {
+        let timer = Timer::new(TimerConfig {
+            notifications: false,
+            ..Default::default()
+        });
+        let mut finished = timer.subscribe_finished();
+        tokio::spawn(timer.clone().run());
+
+        timer.start_countdown(Duration::from_millis(50));
+        let phases = tokio::time::timeout(Duration::from_secs(1), finished.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(phases, (TimerPhase::Countdown, TimerPhase::Idle));
+        assert_eq!(timer.status().phase, TimerPhase::Idle);
+    }
+
+    #[test]
+    fn pomodoro_switches_to_break() {
+        let timer = Timer::new(TimerConfig {
+            notifications: false,
+            ..Default::default()
+        });
+        let changed = timer.subscribe_changes();
+        timer.start_pomodoro();
+        assert!(changed.has_changed().unwrap());
+        timer.update_state(|x| x.remaining = Duration::ZERO);
+
+        assert_eq!(timer.update(), Some((TimerPhase::Work, TimerPhase::Break)));
+        assert_eq!(timer.status().remaining_seconds, 5 * 60);
+        assert_eq!(timer.update(), None);
+    }
+
+    #[test]
+    fn command_arguments_are_checked() {
+        let timer = Timer::new(TimerConfig {
+            notifications: false,
+            ..Default::default()
+        });
+        assert_eq!(
+            timer.handle_command("").unwrap(),
+            r#"{"phase":"idle","remainingSeconds":0,"paused":false}"#
+        );
+
+        timer.handle_command("start 10").unwrap();
+        assert_eq!(timer.status().phase, TimerPhase::Countdown);
+        assert_eq!(timer.status().remaining_seconds, 10 * 60);
+
+        let max_minutes = u32::MAX as u64 / 60;
+        timer.handle_command(&format!("start {}", max_minutes)).unwrap();
+        assert_eq!(timer.status().remaining_seconds as u64, max_minutes * 60);
+
+        for args in [
+            "start 0",
+            "start -1",
+            "start abc",
+            &format!("start {}", max_minutes + 1),
+            &format!("start {}", u64::MAX),
+        ] {
+            assert!(timer.handle_command(args).unwrap_err().starts_with("invalid minutes"), "{}", args);
+        }
+        assert!(timer.handle_command("start").unwrap_err().starts_with("usage"));
+        assert!(timer.handle_command("stop now").unwrap_err().starts_with("usage"));
+
+        timer.handle_command("stop").unwrap();
+        assert_eq!(timer.status().phase, TimerPhase::Idle);
+    }
+}
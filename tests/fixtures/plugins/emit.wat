@@ -0,0 +1,18 @@
+;; Sends a valid message, then a message with results of invalid `emit` calls
+(module
+  (import "qmk" "emit" (func $emit (param i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "\80\01\02")
+  (data (i32.const 16) "\b0\01")
+  (func (export "run")
+    (drop (call $emit (i32.const 0) (i32.const 3)))
+    (i32.store8 (i32.const 32) (i32.const 0x80))
+    ;; out of memory bounds
+    (i32.store8 (i32.const 33) (call $emit (i32.const 65530) (i32.const 10)))
+    (i32.store8 (i32.const 34) (call $emit (i32.const -1) (i32.const 1)))
+    (i32.store8 (i32.const 35) (call $emit (i32.const 0) (i32.const -1)))
+    ;; longer than report, built-in type and empty message
+    (i32.store8 (i32.const 36) (call $emit (i32.const 0) (i32.const 0x7fffffff)))
+    (i32.store8 (i32.const 37) (call $emit (i32.const 16) (i32.const 2)))
+    (i32.store8 (i32.const 38) (call $emit (i32.const 0) (i32.const 0)))
+    (drop (call $emit (i32.const 32) (i32.const 7)))))
@@ -0,0 +1,6 @@
+;; Never calls `wait`, so it runs out of fuel
+(module
+  (memory (export "memory") 1)
+  (func (export "run")
+    (loop $loop
+      (br $loop))))